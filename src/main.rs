@@ -1,27 +1,50 @@
 use crate::{
     fonts::SANS_FONT_PATH,
-    sprites::{BillboardSpritePlugin, FocusType, PlayerFocus, PlayerStart},
+    progress::ProgressPlugin,
+    sprites::{
+        AudioZone, BillboardSpritePlugin, DEFAULT_INTRO_NAME, DialogueState, FocusDetails,
+        FocusType, LastCheckpoint, LevelTuning, PlayerFocus, PlayerStart, SurfaceType, TargetStart,
+    },
     text_parse::parse_random_text,
-    ui::{GameState, LoadingPlugin, MenuPlugin, TEXT_COLOR},
+    ui::{
+        GameSettings, GameState, HudPlugin, InputAction, LoadingPlugin, MenuPlugin, TEXT_COLOR,
+        TransitionFade,
+    },
 };
 use avian3d::{math::*, prelude::*};
 use bevy::{
     input::{common_conditions::input_just_pressed, mouse::AccumulatedMouseMotion},
     prelude::*,
-    window::{CursorGrabMode, CursorOptions},
+    window::{CursorGrabMode, CursorOptions, WindowFocused},
 };
+use bevy_persistent::prelude::*;
 use bevy_seedling::prelude::*;
 use bevy_trenchbroom::prelude::*;
 use bevy_trenchbroom_avian::AvianPhysicsBackend;
 use rand::{Rng, seq::IndexedRandom};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 mod fonts;
+mod progress;
 mod sprites;
 mod text_parse;
 mod ui;
 
 const GRAVITY_MULT: f32 = 16.8;
 
+/// Physics layers. Level geometry is left on the default `World`; the player only
+/// bumps into that, and sprites/triggers are sensors that only see the player
+#[derive(PhysicsLayer, Default, Clone, Copy, Debug)]
+enum GameLayer {
+    #[default]
+    World,
+    Player,
+    Sprite,
+}
+
 // marker component for stuff to destroy between levels
 #[derive(Component)]
 struct LevelStuff;
@@ -45,12 +68,14 @@ fn main() {
     ))
     .add_plugins(MenuPlugin)
     .add_plugins(LoadingPlugin)
+    .add_plugins(HudPlugin)
+    .add_plugins(ProgressPlugin)
     .add_plugins((
         PhysicsPlugins::default(),
-        PhysicsPickingPlugin,
         TrenchBroomPhysicsPlugin::new(AvianPhysicsBackend),
     ))
     .insert_resource(Gravity(Vec3::NEG_Y * GRAVITY_MULT))
+    .init_resource::<MovementTuning>()
     .add_plugins(
         TrenchBroomPlugins(
             TrenchBroomConfig::new("my_favorite_nightmare").default_solid_scene_hooks(|| {
@@ -71,6 +96,9 @@ fn main() {
     app.run();
 }
 
+// One-shot SamplePlayers don't need cleaning up by hand, seedling's default
+// PlaybackSettings despawn them once they finish. Only looping ones (the bgm) stick
+// around, and those go with LevelStuff or a FadeOut.
 struct AudioPlugin;
 impl Plugin for AudioPlugin {
     fn build(&self, app: &mut App) {
@@ -81,17 +109,159 @@ impl Plugin for AudioPlugin {
             },
             play_level_intro_stinger,
         )
+        .init_resource::<WalkingCadence>()
+        .init_resource::<FootstepSounds>()
+        .init_resource::<ZoneMix>()
+        .add_systems(Startup, spawn_zone_effects)
         .add_systems(FixedUpdate, play_walking_noises)
+        .add_systems(Update, (fade_out_audio, blend_audio_zones))
         .add_observer(on_stinger_finished);
     }
 }
 
+// marker for the looping level bgm, gets faded out rather than despawned with the level
+#[derive(Component)]
+struct LevelBgm;
+
+const BGM_FADE_OUT_S: f32 = 0.4;
+
+// fades a sample's volume to zero, then despawns it
+#[derive(Component)]
+struct FadeOut {
+    timer: Timer,
+    start_volume: Option<f32>,
+}
+impl Default for FadeOut {
+    fn default() -> Self {
+        FadeOut {
+            timer: Timer::from_seconds(BGM_FADE_OUT_S, TimerMode::Once),
+            start_volume: None,
+        }
+    }
+}
+
+fn fade_out_audio(
+    mut fading: Query<(Entity, &mut SamplePlayer, &mut FadeOut)>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (ent, mut player, mut fade) in &mut fading {
+        let start_volume = *fade.start_volume.get_or_insert(player.volume.linear());
+        if fade.timer.tick(time.delta()).is_finished() {
+            commands.entity(ent).despawn();
+        } else {
+            player.volume = Volume::Linear(start_volume * fade.timer.fraction_remaining());
+        }
+    }
+}
+
+// wide open filter and no echo, for when the player isn't in any AudioZone
+const DRY_LOWPASS_HZ: f32 = 20_000.0;
+// how quickly the mix eases toward the zone the player's in, per second
+const AUDIO_ZONE_BLEND_RATE: f32 = 3.0;
+
+#[derive(Component)]
+struct ZoneLowPass;
+
+#[derive(Component)]
+struct ZoneReverbSend;
+
+/// Where the environmental effects currently sit, eased toward the player's AudioZone
+#[derive(Resource)]
+struct ZoneMix {
+    reverb: f32,
+    lowpass: f32,
+}
+impl Default for ZoneMix {
+    fn default() -> Self {
+        ZoneMix {
+            reverb: 0.0,
+            lowpass: DRY_LOWPASS_HZ,
+        }
+    }
+}
+
+// Reroutes the main bus (music and sfx both end up there) through a lowpass, with a
+// reverb send off the back of it. Both sit at dry until an AudioZone turns them up.
+fn spawn_zone_effects(main_bus: Single<Entity, With<MainBus>>, mut commands: Commands) {
+    let send = commands
+        .spawn((
+            VolumeNode {
+                volume: Volume::SILENT,
+                ..default()
+            },
+            ZoneReverbSend,
+        ))
+        .id();
+    commands.entity(send).connect(AudioGraphOutput);
+    let reverb = commands.spawn(FreeverbNode::default()).id();
+    commands.entity(reverb).connect(send);
+    let lowpass = commands
+        .spawn((
+            LowPassNode {
+                frequency: DRY_LOWPASS_HZ,
+            },
+            ZoneLowPass,
+        ))
+        .id();
+    commands
+        .entity(lowpass)
+        .connect(AudioGraphOutput)
+        .connect(reverb);
+    commands
+        .entity(*main_bus)
+        .disconnect(AudioGraphOutput)
+        .connect(lowpass);
+}
+
+fn blend_audio_zones(
+    zones: Query<(&AudioZone, &CollidingEntities)>,
+    player: Query<Entity, With<PlayerCamera>>,
+    mut lowpass_nodes: Query<&mut LowPassNode, With<ZoneLowPass>>,
+    mut send_nodes: Query<&mut VolumeNode, With<ZoneReverbSend>>,
+    mut mix: ResMut<ZoneMix>,
+    time: Res<Time>,
+) {
+    // overlapping zones go with whichever's most muffled, no player (no level) is dry
+    let (target_reverb, target_lowpass) = zones
+        .iter()
+        .filter(|(_, colliding)| player.iter().any(|ent| colliding.0.contains(&ent)))
+        .fold((0.0_f32, DRY_LOWPASS_HZ), |(reverb, lowpass), (zone, _)| {
+            (
+                reverb.max(zone.reverb.clamp(0.0, 1.0)),
+                lowpass.min(zone.lowpass.clamp(1.0, DRY_LOWPASS_HZ)),
+            )
+        });
+    let t = 1.0 - (-AUDIO_ZONE_BLEND_RATE * time.delta_secs()).exp();
+    if (mix.reverb - target_reverb).abs() < f32::EPSILON
+        && (mix.lowpass - target_lowpass).abs() < f32::EPSILON
+    {
+        return;
+    }
+    mix.reverb = mix.reverb.lerp(target_reverb, t);
+    // ears hear pitch logarithmically, so sweep the cutoff in octaves
+    mix.lowpass = mix.lowpass.log2().lerp(target_lowpass.log2(), t).exp2();
+
+    for mut lowpass in &mut lowpass_nodes {
+        lowpass.frequency = mix.lowpass;
+    }
+    for mut send in &mut send_nodes {
+        send.volume = Volume::Linear(mix.reverb);
+    }
+}
+
 #[derive(Component)]
 struct OnIntroStingerFinished;
 
-fn play_level_intro_stinger(mut commands: Commands, server: Res<AssetServer>) {
+fn play_level_intro_stinger(
+    mut commands: Commands,
+    server: Res<AssetServer>,
+    level_start: Res<LevelStartLocation>,
+) {
+    // bgm starts from on_stinger_finished whichever stinger this is
     commands.spawn((
-        SamplePlayer::new(server.load("sounds/intro1.wav")),
+        SamplePlayer::new(server.load(format!("sounds/{}.wav", level_start.intro_name))),
+        MusicPool,
         OnIntroStingerFinished,
     ));
 }
@@ -107,6 +277,8 @@ fn on_stinger_finished(
         SamplePlayer::new(server.load(format!("sounds/{}.wav", level_start.bgm_name)))
             .with_volume(Volume::from_percent(level_start.bgm_vol))
             .looping(),
+        MusicPool,
+        LevelBgm,
         LevelStuff,
     ));
 }
@@ -115,36 +287,121 @@ fn on_stinger_finished(
 struct WalkingSFX;
 
 const WALKING_NOISE_MIN_VEL: f32 = 2.5;
+// scalar fed to get_scalar_boosted_rand_sfx_speed, bigger = wider pitch spread between steps
+const WALKING_PITCH_JITTER: f32 = 1.0;
+
+// time between footsteps, shortened the faster the player is going
+#[derive(Resource)]
+struct WalkingCadence(Timer);
+impl Default for WalkingCadence {
+    fn default() -> Self {
+        WalkingCadence(Timer::from_seconds(
+            WALKING_CADENCE_SLOW_S,
+            TimerMode::Repeating,
+        ))
+    }
+}
+
+const WALKING_CADENCE_SLOW_S: f32 = 0.6;
+const WALKING_CADENCE_FAST_S: f32 = 0.25;
+// roughly the top xz speeds walking and sprinting, PLAYER_SPEED and that times
+// PLAYER_SPRINT_BOOST, keep them in step
+const WALKING_CADENCE_SLOW_VEL: f32 = 7.5;
+const WALKING_CADENCE_FAST_VEL: f32 = 11.5;
 
+fn get_walking_cadence_secs(speed: f32) -> f32 {
+    let t = ((speed - WALKING_CADENCE_SLOW_VEL)
+        / (WALKING_CADENCE_FAST_VEL - WALKING_CADENCE_SLOW_VEL))
+        .clamp(0.0, 1.0);
+    WALKING_CADENCE_SLOW_S.lerp(WALKING_CADENCE_FAST_S, t)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn play_walking_noises(
-    player_vels: Query<&LinearVelocity, With<PlayerCamera>>,
-    playing_walking_samples: Query<&bevy_seedling::sample::PlaybackSettings, With<WalkingSFX>>,
+    player_vels: Query<(&LinearVelocity, &ShapeHits), With<PlayerCamera>>,
+    surfaces: Query<&SurfaceType>,
+    colliders: Query<&ColliderOf>,
+    footsteps: Res<FootstepSounds>,
+    mut cadence: ResMut<WalkingCadence>,
+    time: Res<Time>,
     mut commands: Commands,
     server: Res<AssetServer>,
 ) {
-    for vel in player_vels {
-        if get_xz_len(&vel) > WALKING_NOISE_MIN_VEL {
-            // only fire if none are playing
-            if playing_walking_samples.is_empty() {
-                let sfx_path = get_random_walking_sound_path();
-                commands.spawn((SamplePlayer::new(server.load(sfx_path)), WalkingSFX));
-            }
+    for (vel, ground_hits) in player_vels {
+        let speed = get_xz_len(&vel);
+        if speed <= WALKING_NOISE_MIN_VEL {
+            // primed so the first step fires as soon as we start moving again
+            let duration = cadence.0.duration();
+            cadence.0.set_elapsed(duration);
+            continue;
+        }
+
+        cadence
+            .0
+            .set_duration(Duration::from_secs_f32(get_walking_cadence_secs(speed)));
+        if cadence.0.tick(time.delta()).just_finished() {
+            // whatever's closest under our feet, by the body it belongs to
+            let surface = ground_hits
+                .iter()
+                .min_by(|a, b| a.distance.total_cmp(&b.distance))
+                .and_then(|hit| {
+                    let body = colliders.get(hit.entity).map_or(hit.entity, |of| of.body);
+                    surfaces.get(body).ok()
+                })
+                .map_or(DEFAULT_SURFACE, |surface| surface.surface.as_str());
+            let sfx_path = footsteps.random_path(surface);
+            commands.spawn((
+                SamplePlayer::new(server.load(sfx_path)),
+                bevy_seedling::sample::PlaybackSettings {
+                    speed: get_scalar_boosted_rand_sfx_speed(WALKING_PITCH_JITTER),
+                    ..default()
+                },
+                WalkingSFX,
+            ));
         }
     }
 }
 
-fn get_random_walking_sound_path() -> String {
-    let mut rng = rand::rng();
-    let noises = vec![
-        "sounds/squelch1.wav",
-        "sounds/squelch2.wav",
-        "sounds/squelch3.wav",
-        "sounds/squelch4.wav",
-        "sounds/squelch5.wav",
-        "sounds/squelch6.wav",
-        "sounds/squelch7.wav",
-    ];
-    noises.choose(&mut rng).unwrap().to_string()
+// untagged ground (and anything without its own sounds) is flesh
+const DEFAULT_SURFACE: &str = "flesh";
+
+/// Footstep sounds for each `SurfaceType` name
+#[derive(Resource)]
+struct FootstepSounds(HashMap<String, Vec<&'static str>>);
+
+impl Default for FootstepSounds {
+    fn default() -> Self {
+        FootstepSounds(HashMap::from([
+            (
+                DEFAULT_SURFACE.to_string(),
+                vec![
+                    "sounds/squelch1.wav",
+                    "sounds/squelch2.wav",
+                    "sounds/squelch3.wav",
+                    "sounds/squelch4.wav",
+                    "sounds/squelch5.wav",
+                    "sounds/squelch6.wav",
+                    "sounds/squelch7.wav",
+                ],
+            ),
+            (
+                "tile".to_string(),
+                vec!["sounds/step1.wav", "sounds/step2.wav"],
+            ),
+        ]))
+    }
+}
+
+impl FootstepSounds {
+    fn random_path(&self, surface: &str) -> String {
+        let mut rng = rand::rng();
+        self.0
+            .get(surface)
+            .or_else(|| self.0.get(DEFAULT_SURFACE))
+            .and_then(|noises| noises.choose(&mut rng))
+            .unwrap()
+            .to_string()
+    }
 }
 
 const DEFAULT_PLAYER_START_LOC: Vec3 = Vec3::new(1.375, 0.9, 0.6);
@@ -153,48 +410,70 @@ const DEFAULT_PLAYER_START_LOC: Vec3 = Vec3::new(1.375, 0.9, 0.6);
 struct CameraPlugin;
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource::<LevelStartLocation>(LevelStartLocation {
-            spawn: DEFAULT_PLAYER_START_LOC.clone(),
-            bgm_name: "bgm1".to_string(),
-            bgm_vol: 50.,
-            bg_color: Color::srgba(0.35, 0.48, 0.66, 1.0),
-        })
-        .add_systems(
-            OnTransition {
-                exited: GameState::Loading,
-                entered: GameState::InGame,
-            },
-            spawn_camera,
-        )
-        .add_systems(
-            OnTransition {
-                exited: GameState::InGame,
-                entered: GameState::Loading,
-            },
-            reset_focus,
-        )
-        .add_systems(
-            FixedUpdate,
-            (
-                player_camera_movement.run_if(in_state(GameState::InGame)),
-                update_player_start_location.run_if(in_state(GameState::Loading)),
-                update_grounded.run_if(in_state(GameState::InGame)),
-                debug_commands_and_oob_reset.run_if(in_state(GameState::InGame)),
-            ),
-        )
-        .add_systems(
-            Update,
-            (
-                update_camera_transform.run_if(in_state(GameState::InGame)),
-                capture_cursor
-                    .run_if(input_just_pressed(MouseButton::Left))
-                    .run_if(in_state(GameState::InGame)),
-                release_cursor
-                    .run_if(input_just_pressed(KeyCode::Escape))
+        app.init_resource::<LevelStartLocation>()
+            .init_resource::<PlayerStartTimeout>()
+            .init_resource::<Sprinting>()
+            .init_resource::<CameraShake>()
+            .init_resource::<LookEnabled>()
+            .init_resource::<CameraConstraints>()
+            .add_systems(
+                OnTransition {
+                    exited: GameState::Loading,
+                    entered: GameState::InGame,
+                },
+                spawn_camera,
+            )
+            .add_systems(
+                OnTransition {
+                    exited: GameState::InGame,
+                    entered: GameState::Loading,
+                },
+                reset_focus,
+            )
+            .add_systems(OnEnter(GameState::Loading), reset_player_start_timeout)
+            // Escape is only handled by the menu toggle, the cursor just follows the menu
+            .add_systems(OnEnter(GameState::Menu), (release_cursor, reset_focus))
+            .add_systems(OnExit(GameState::Menu), capture_cursor)
+            .add_systems(
+                FixedUpdate,
+                (
+                    player_crouch.run_if(in_state(GameState::InGame)),
+                    player_camera_movement.run_if(in_state(GameState::InGame)),
+                    update_player_start_location.run_if(in_state(GameState::Loading)),
+                    fall_back_to_default_start
+                        .after(update_player_start_location)
+                        .run_if(in_state(GameState::Loading)),
+                    update_grounded.run_if(in_state(GameState::InGame)),
+                    debug_commands_and_oob_reset.run_if(in_state(GameState::InGame)),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    update_camera_transform
+                        .run_if(in_state(GameState::InGame))
+                        .run_if(not_in_dialogue),
+                    update_sprint_fov.run_if(in_state(GameState::InGame)),
+                    apply_camera_shake
+                        .after(update_camera_transform)
+                        .run_if(in_state(GameState::InGame)),
+                    capture_cursor
+                        .run_if(input_just_pressed(MouseButton::Left))
+                        .run_if(in_state(GameState::InGame)),
+                    update_action_text.run_if(in_state(GameState::InGame)),
+                    release_cursor_on_unfocus,
+                ),
+            );
+        // level inspection, not something players should stumble into
+        if cfg!(debug_assertions) {
+            app.add_systems(
+                Update,
+                toggle_noclip
+                    .run_if(input_just_pressed(NOCLIP_KEY))
                     .run_if(in_state(GameState::InGame)),
-                update_action_text.run_if(in_state(GameState::InGame)),
-            ),
-        );
+            )
+            .add_systems(FixedUpdate, noclip_fly.run_if(in_state(GameState::InGame)));
+        }
     }
 }
 
@@ -207,15 +486,22 @@ struct ActionText;
 fn update_action_text(
     existing_action_text: Query<Entity, With<ActionText>>,
     focus: Res<PlayerFocus>,
+    dialogue: Res<DialogueState>,
     mut commands: Commands,
     server: Res<AssetServer>,
+    mut cached_prompt: Local<CachedActionPrompt>,
 ) {
     let current_focus = focus.0.clone();
 
     // Check if action text exists
     let action_text_exists = !existing_action_text.is_empty();
+    // keep the prompt up for the whole conversation, even if the rat leaves focus
+    let should_show = current_focus
+        .as_ref()
+        .is_some_and(|focus| focus.focus_type.is_interactive())
+        || dialogue.is_active();
 
-    match (action_text_exists, current_focus.is_some()) {
+    match (action_text_exists, should_show) {
         // Exists but shouldn't - despawn it
         (true, false) => {
             for ent in &existing_action_text {
@@ -224,59 +510,92 @@ fn update_action_text(
         }
         // Doesn't exist but should - spawn it
         (false, true) => {
-            let text = match current_focus.unwrap().focus_type {
-                FocusType::Hole => get_action_str_hole(),
-                FocusType::NPC => get_action_str_npc(),
+            // same thing as last time (or mid conversation with nothing focused), same words
+            let focus_entity = current_focus.as_ref().map(|focus| focus.entity);
+            if let Some(text) = &cached_prompt.text
+                && (focus_entity.is_none() || focus_entity == cached_prompt.focus)
+            {
+                spawn_action_text(&mut commands, &server, text);
+                return;
+            }
+            let text = match &current_focus {
+                // the map gets first say
+                Some(FocusDetails {
+                    action_prompt: Some(prompt),
+                    ..
+                }) => parse_random_text(prompt),
+                Some(FocusDetails {
+                    focus_type: FocusType::Hole | FocusType::Door,
+                    ..
+                }) => parse_random_text(HOLE_ACTION_TEXT),
+                Some(FocusDetails {
+                    focus_type: FocusType::Lever,
+                    ..
+                }) => parse_random_text(LEVER_ACTION_TEXT),
+                // only NPCs keep the prompt up with nothing focused (mid conversation)
+                _ => parse_random_text(NPC_ACTION_TEXT),
+            };
+            spawn_action_text(&mut commands, &server, &text);
+            *cached_prompt = CachedActionPrompt {
+                focus: focus_entity,
+                text: Some(text),
             };
-            commands.spawn((
-                Node {
-                    top: vh(50),
-                    left: vw(50),
-                    position_type: PositionType::Absolute,
-                    display: Display::Flex,
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
-                    padding: UiRect::axes(px(20), px(10)),
-                    margin: UiRect {
-                        left: px(-36),
-                        right: px(0),
-                        top: px(0),
-                        bottom: px(0),
-                    },
-                    ..default()
-                },
-                BackgroundColor {
-                    0: Color::Oklcha(Oklcha::new(0.1788, 0.0099, 288.85, 0.9)),
-                },
-                ActionText,
-                LevelStuff,
-                children![(
-                    Text::new(text),
-                    TextColor(TEXT_COLOR),
-                    TextFont {
-                        font: server.load(SANS_FONT_PATH),
-                        font_size: 34.0,
-                        ..default()
-                    },
-                )],
-            ));
         }
         _ => {}
     }
 }
 
-fn get_action_str_npc() -> String {
-    parse_random_text(
-        "<TALK:80|TALK!!:10|TALK...|TaLK|rat chat?:5|talk to POOPY rat|chat up this rodent playa?|kiss this rat with language|RAT>",
-    )
+/// Last rolled action prompt and what it was for, so it only rerolls when focus moves on
+#[derive(Default)]
+struct CachedActionPrompt {
+    focus: Option<Entity>,
+    text: Option<String>,
 }
 
-fn get_action_str_hole() -> String {
-    parse_random_text(
-        "<HOLE:80|HOLE?:10|HOLE?:5|back in the hole don't get too excited|you could go in this hole>",
-    )
+/// The prompt in the middle of the screen saying what clicking will do
+fn spawn_action_text(commands: &mut Commands, server: &AssetServer, text: &str) -> Entity {
+    commands
+        .spawn((
+            Node {
+                top: vh(50),
+                left: vw(50),
+                position_type: PositionType::Absolute,
+                display: Display::Flex,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                padding: UiRect::axes(px(20), px(10)),
+                margin: UiRect {
+                    left: px(-36),
+                    right: px(0),
+                    top: px(0),
+                    bottom: px(0),
+                },
+                ..default()
+            },
+            BackgroundColor {
+                0: Color::Oklcha(Oklcha::new(0.1788, 0.0099, 288.85, 0.9)),
+            },
+            ActionText,
+            LevelStuff,
+            children![(
+                Text::new(text),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: server.load(SANS_FONT_PATH),
+                    font_size: 34.0,
+                    ..default()
+                },
+            )],
+        ))
+        .id()
 }
 
+// default action prompts, NPCs can swap theirs out from the map
+const NPC_ACTION_TEXT: &str = "<TALK:80|TALK!!:10|TALK...|TaLK|rat chat?:5|talk to POOPY rat|chat up this rodent playa?|kiss this rat with language|RAT>";
+const HOLE_ACTION_TEXT: &str =
+    "<HOLE:80|HOLE?:10|HOLE?:5|back in the hole don't get too excited|you could go in this hole>";
+const LEVER_ACTION_TEXT: &str = "<PULL:80|PULL?:10|yank it|flip the thing>";
+
 fn reset_focus(mut focus: ResMut<PlayerFocus>) {
     focus.0 = None;
 }
@@ -290,23 +609,43 @@ struct PlayerCamera;
 #[derive(Resource, Clone, Debug)]
 struct LevelStartLocation {
     pub spawn: Vec3,
+    pub start_name: String,
     pub bg_color: Color,
     pub bgm_name: String,
     pub bgm_vol: f32,
+    pub intro_name: String,
 }
 
-fn spawn_camera(mut commands: Commands, level_start: Res<LevelStartLocation>) {
-    let player_collider = Collider::cuboid(0.1, 0.5, 0.1);
-    let mut caster_shape = player_collider.clone();
-    caster_shape.set_scale(Vector::ONE * 0.99, 10);
+impl Default for LevelStartLocation {
+    fn default() -> Self {
+        LevelStartLocation {
+            spawn: DEFAULT_PLAYER_START_LOC,
+            start_name: String::new(),
+            bgm_name: "bgm1".to_string(),
+            bgm_vol: 50.,
+            intro_name: DEFAULT_INTRO_NAME.to_string(),
+            bg_color: Color::srgba(0.35, 0.48, 0.66, 1.0),
+        }
+    }
+}
 
+fn spawn_camera(
+    mut commands: Commands,
+    level_start: Res<LevelStartLocation>,
+    settings: Res<Persistent<GameSettings>>,
+) {
     commands.spawn((
         PlayerCamera,
         Camera3d::default(),
+        SpatialListener3D::default(),
         Camera {
             order: 1,
             ..default()
         },
+        Projection::Perspective(PerspectiveProjection {
+            fov: settings.fov().to_radians(),
+            ..default()
+        }),
         Transform::from_xyz(
             level_start.spawn.x,
             level_start.spawn.y,
@@ -315,13 +654,18 @@ fn spawn_camera(mut commands: Commands, level_start: Res<LevelStartLocation>) {
         .looking_at(Vec3::new(0., level_start.spawn.y, 0.), Vec3::Y),
         RigidBody::Dynamic,
         ShapeCaster::new(
-            caster_shape,
+            player_caster_shape(PLAYER_HEIGHT),
             Vector::ZERO,
             Quaternion::default(),
             Dir3::NEG_Y,
         )
-        .with_max_distance(0.2),
-        player_collider,
+        .with_max_distance(0.2)
+        .with_query_filter(SpatialQueryFilter::from_mask(GameLayer::World)),
+        (
+            player_collider(PLAYER_HEIGHT),
+            CollisionLayers::new(GameLayer::Player, [GameLayer::World, GameLayer::Sprite]),
+        ),
+        JumpState::default(),
         TransformInterpolation,
         CollidingEntities::default(),
         LockedAxes::ROTATION_LOCKED,
@@ -339,6 +683,72 @@ fn spawn_camera(mut commands: Commands, level_start: Res<LevelStartLocation>) {
     ));
 }
 
+const PLAYER_HEIGHT: f32 = 0.5;
+const PLAYER_CROUCH_HEIGHT: f32 = 0.25;
+// how far the body drops when crouching so the feet stay put
+const PLAYER_CROUCH_DROP: f32 = (PLAYER_HEIGHT - PLAYER_CROUCH_HEIGHT) / 2.0;
+
+fn player_collider(height: f32) -> Collider {
+    Collider::cuboid(0.1, height, 0.1)
+}
+
+// Slightly smaller than the collider so ground checks don't catch walls
+fn player_caster_shape(height: f32) -> Collider {
+    let mut caster_shape = player_collider(height);
+    caster_shape.set_scale(Vector::ONE * 0.99, 10);
+    caster_shape
+}
+
+#[derive(Component)]
+struct Crouching;
+
+fn player_crouch(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    settings: Res<Persistent<GameSettings>>,
+    spatial_query: SpatialQuery,
+    mut query: Query<
+        (
+            Entity,
+            &mut Collider,
+            &mut ShapeCaster,
+            &mut Transform,
+            Has<Crouching>,
+        ),
+        (With<PlayerCamera>, Without<Noclip>),
+    >,
+) {
+    let wants_crouch = input.pressed(settings.key_bindings().key(InputAction::Crouch));
+    for (entity, mut collider, mut caster, mut transform, is_crouching) in &mut query {
+        if wants_crouch && !is_crouching {
+            *collider = player_collider(PLAYER_CROUCH_HEIGHT);
+            caster.shape = player_caster_shape(PLAYER_CROUCH_HEIGHT);
+            transform.translation.y -= PLAYER_CROUCH_DROP;
+            commands.entity(entity).insert(Crouching);
+        } else if !wants_crouch && is_crouching {
+            // stay crouched if there's something overhead to stand up into
+            let blocked = spatial_query
+                .cast_shape(
+                    &player_caster_shape(PLAYER_CROUCH_HEIGHT),
+                    transform.translation,
+                    transform.rotation,
+                    Dir3::Y,
+                    &ShapeCastConfig::from_max_distance(PLAYER_CROUCH_DROP * 2.0),
+                    &SpatialQueryFilter::from_mask(GameLayer::World)
+                        .with_excluded_entities([entity]),
+                )
+                .is_some();
+            if blocked {
+                continue;
+            }
+            *collider = player_collider(PLAYER_HEIGHT);
+            caster.shape = player_caster_shape(PLAYER_HEIGHT);
+            transform.translation.y += PLAYER_CROUCH_DROP;
+            commands.entity(entity).remove::<Crouching>();
+        }
+    }
+}
+
 const MAX_SLOPE_ANGLE: f32 = 45.;
 
 fn update_grounded(
@@ -379,62 +789,155 @@ fn get_random_oof_sound_path() -> String {
     noises.choose(&mut rng).unwrap().to_string()
 }
 
-const PLAYER_SPEED: f32 = 3.5;
+// top horizontal speeds, the footstep cadence speeds are tuned to these
+const PLAYER_SPEED: f32 = 7.5;
 const PLAYER_JUMP_SPEED: f32 = 4.0;
 const PLAYER_SPRINT_BOOST: f32 = 1.5;
-const PLAYER_SLOWDOWN_MULT: f32 = 20.0;
-const PLAYER_IN_AIR_SLOWDOWN_MULT: f32 = 15.;
+const PLAYER_CROUCH_SPEED: f32 = 3.0;
+// how quickly horizontal velocity catches up to where the input wants it, per second
+const PLAYER_ACCEL: f32 = 15.0;
+const PLAYER_DECEL: f32 = 20.0;
+const PLAYER_AIR_ACCEL: f32 = 10.0;
+
+// Movement numbers for the current level, from its LevelTuning if it has one
+#[derive(Resource, Clone, Copy)]
+struct MovementTuning {
+    gravity: f32,
+    player_speed: f32,
+    sprint_boost: f32,
+}
+impl Default for MovementTuning {
+    fn default() -> Self {
+        MovementTuning {
+            gravity: GRAVITY_MULT,
+            player_speed: PLAYER_SPEED,
+            sprint_boost: PLAYER_SPRINT_BOOST,
+        }
+    }
+}
+
+// forgiveness windows for jumping
+const COYOTE_TIME_S: f32 = 0.1;
+const JUMP_BUFFER_S: f32 = 0.1;
 
+// Seconds since the player was last on the ground and last pressed jump
+#[derive(Component)]
+struct JumpState {
+    since_grounded: f32,
+    since_jump_pressed: f32,
+}
+impl Default for JumpState {
+    fn default() -> Self {
+        JumpState {
+            since_grounded: f32::INFINITY,
+            since_jump_pressed: f32::INFINITY,
+        }
+    }
+}
+
+// Whether the player is currently sprinting, either held or toggled on
+#[derive(Resource, Default)]
+struct Sprinting(bool);
+
+#[allow(clippy::too_many_arguments)]
 fn player_camera_movement(
-    mut query: Query<(&mut LinearVelocity, &Transform, Has<Grounded>), With<PlayerCamera>>,
+    mut query: Query<
+        (
+            &mut LinearVelocity,
+            &mut JumpState,
+            &Transform,
+            Has<Grounded>,
+            Has<Crouching>,
+        ),
+        (With<PlayerCamera>, Without<Noclip>),
+    >,
     time: Res<Time>,
     input: Res<ButtonInput<KeyCode>>,
     mut commands: Commands,
     server: Res<AssetServer>,
+    settings: Res<Persistent<GameSettings>>,
+    tuning: Res<MovementTuning>,
+    mut sprinting: ResMut<Sprinting>,
+    dialogue: Res<DialogueState>,
 ) {
-    for (mut lin_vel, camera, is_grounded) in &mut query {
+    let keys = settings.key_bindings();
+    // stand still and listen while someone's talking, momentum eases off like normal
+    let listening = dialogue.is_active();
+    let held = |action| !listening && input.pressed(keys.key(action));
+    let tapped = |action| !listening && input.just_pressed(keys.key(action));
+    for (mut lin_vel, mut jump_state, camera, is_grounded, is_crouching) in &mut query {
         // build movement vec from current inputs
         let mut movement_vel = Vec3::ZERO;
-        if input.pressed(KeyCode::KeyW) {
+        if held(InputAction::Forward) {
             movement_vel += Vec3::NEG_Z
         }
-        if input.pressed(KeyCode::KeyS) {
+        if held(InputAction::Back) {
             movement_vel += Vec3::Z
         }
-        if input.pressed(KeyCode::KeyA) {
+        if held(InputAction::Left) {
             movement_vel += Vec3::NEG_X
         }
-        if input.pressed(KeyCode::KeyD) {
+        if held(InputAction::Right) {
             movement_vel += Vec3::X
         }
-        movement_vel = movement_vel.normalize_or_zero();
-        movement_vel *= PLAYER_SPEED;
-        if input.pressed(KeyCode::ShiftLeft) {
-            movement_vel *= PLAYER_SPRINT_BOOST;
+        movement_vel = movement_vel.normalize_or_zero() * tuning.player_speed;
+        if settings.sprint_toggle_enabled() {
+            if tapped(InputAction::Sprint) {
+                sprinting.0 = !sprinting.0;
+            }
+            // don't keep sprinting while standing still
+            if movement_vel == Vec3::ZERO {
+                sprinting.0 = false;
+            }
+        } else {
+            sprinting.0 = held(InputAction::Sprint);
         }
-        movement_vel = camera.rotation * movement_vel;
+        if sprinting.0 {
+            movement_vel *= tuning.sprint_boost;
+        }
+        if is_crouching {
+            movement_vel = movement_vel.clamp_length_max(PLAYER_CROUCH_SPEED);
+        }
+        // only yaw steers, looking up or down doesn't slow you
+        let (yaw, _, _) = camera.rotation.to_euler(EulerRot::YXZ);
+        let target_vel = Quat::from_rotation_y(yaw) * movement_vel;
 
-        // Add to current velocity
-        lin_vel.0.x += movement_vel.adjust_precision().x;
-        lin_vel.0.z += movement_vel.adjust_precision().z;
+        // Ease horizontal velocity toward the target, with less control in the air
+        let rate = if !is_grounded {
+            PLAYER_AIR_ACCEL
+        } else if target_vel == Vec3::ZERO {
+            PLAYER_DECEL
+        } else {
+            PLAYER_ACCEL
+        };
+        let horizontal_vel = ease_toward(
+            Vec2::new(lin_vel.0.x, lin_vel.0.z),
+            Vec2::new(target_vel.x, target_vel.z),
+            rate,
+            time.delta_secs(),
+        );
+        lin_vel.0.x = horizontal_vel.x;
+        lin_vel.0.z = horizontal_vel.y;
 
         let current_speed = get_xz_len(&lin_vel);
-        if current_speed > 0.0 {
-            // Apply friction, though only in X and Z dirs
-            let mult = if is_grounded {
-                PLAYER_SLOWDOWN_MULT
-            } else {
-                PLAYER_IN_AIR_SLOWDOWN_MULT
-            } * time.delta_secs().adjust_precision();
-            lin_vel.0.x =
-                lin_vel.0.x / current_speed * (current_speed - current_speed * mult).max(0.0);
-            lin_vel.0.z =
-                lin_vel.0.z / current_speed * (current_speed - current_speed * mult).max(0.0);
-        }
 
         // handle vert component
-        // only jump if on the ground
-        if input.just_pressed(KeyCode::Space) && is_grounded {
+        // jump if on the ground, or were just a moment ago, and pressed jump recently
+        if is_grounded {
+            jump_state.since_grounded = 0.0;
+        } else {
+            jump_state.since_grounded += time.delta_secs();
+        }
+        if tapped(InputAction::Jump) {
+            jump_state.since_jump_pressed = 0.0;
+        } else {
+            jump_state.since_jump_pressed += time.delta_secs();
+        }
+        if jump_state.since_jump_pressed <= JUMP_BUFFER_S
+            && jump_state.since_grounded <= COYOTE_TIME_S
+        {
+            // use both up so one press is one jump
+            *jump_state = JumpState::default();
             commands.spawn((
                 SamplePlayer::new(server.load("sounds/boing.wav")),
                 bevy_seedling::sample::PlaybackSettings {
@@ -449,6 +952,10 @@ fn player_camera_movement(
     }
 }
 
+/// Random playback speed for a one-shot sfx, picked between `1 + 0.05 * scalar` and
+/// `max(0.15 * scalar, 1.125)`. Small scalars stay just above normal pitch (1.0-1.125),
+/// bigger ones pitch up and spread further, e.g. the jump boing at full sprint.
+/// Returns f64 since that's what seedling's `PlaybackSettings::speed` takes
 pub fn get_scalar_boosted_rand_sfx_speed(scalar: f32) -> f64 {
     let mut rng = rand::rng();
     let adjusted_scalar = scalar * 0.15;
@@ -463,30 +970,121 @@ pub fn get_scalar_boosted_rand_sfx_speed(scalar: f32) -> f64 {
     }) as f64
 }
 
+/// Exponential ease from `current` toward `target` at `rate` per second. Two ticks of
+/// `dt / 2` land exactly where one tick of `dt` does, so top speed (always `target`) and
+/// stopping distance (about `speed / rate`) come out the same at any FixedUpdate rate
+fn ease_toward(current: Vec2, target: Vec2, rate: f32, dt: f32) -> Vec2 {
+    current.lerp(target, 1.0 - (-rate * dt).exp())
+}
+
 fn get_xz_len(input: &Vec3) -> f32 {
     (input.x * input.x + input.z * input.z).sqrt()
 }
 
+const NOCLIP_KEY: KeyCode = KeyCode::F4;
+const NOCLIP_SPEED: f32 = 10.0;
+
+// Player flies free of physics while this is on (debug builds only)
+#[derive(Component)]
+struct Noclip;
+
+fn toggle_noclip(
+    player: Single<(Entity, &mut LinearVelocity, Has<Noclip>), With<PlayerCamera>>,
+    mut commands: Commands,
+) {
+    let (entity, mut lin_vel, is_noclip) = player.into_inner();
+    if is_noclip {
+        commands
+            .entity(entity)
+            .remove::<(Noclip, RigidBodyDisabled, ColliderDisabled)>();
+    } else {
+        lin_vel.0 = Vec3::ZERO;
+        commands
+            .entity(entity)
+            .insert((Noclip, RigidBodyDisabled, ColliderDisabled));
+    }
+    info!("noclip {}", if is_noclip { "off" } else { "on" });
+}
+
+fn noclip_fly(
+    mut query: Query<&mut Transform, (With<PlayerCamera>, With<Noclip>)>,
+    input: Res<ButtonInput<KeyCode>>,
+    settings: Res<Persistent<GameSettings>>,
+    time: Res<Time>,
+) {
+    let keys = settings.key_bindings();
+    for mut transform in &mut query {
+        // fly wherever the camera points, pitch included
+        let mut dir = Vec3::ZERO;
+        if input.pressed(keys.key(InputAction::Forward)) {
+            dir += *transform.forward();
+        }
+        if input.pressed(keys.key(InputAction::Back)) {
+            dir -= *transform.forward();
+        }
+        if input.pressed(keys.key(InputAction::Left)) {
+            dir -= *transform.right();
+        }
+        if input.pressed(keys.key(InputAction::Right)) {
+            dir += *transform.right();
+        }
+        if input.pressed(keys.key(InputAction::Jump)) {
+            dir += Vec3::Y;
+        }
+        if input.pressed(keys.key(InputAction::Crouch)) {
+            dir -= Vec3::Y;
+        }
+        let speed = if input.pressed(keys.key(InputAction::Sprint)) {
+            NOCLIP_SPEED * PLAYER_SPRINT_BOOST
+        } else {
+            NOCLIP_SPEED
+        };
+        transform.translation += dir.normalize_or_zero() * speed * time.delta_secs();
+    }
+}
+
 const MIN_Y: f32 = 0.0;
 fn debug_commands_and_oob_reset(
-    mut player_tf_query: Query<(&mut Transform, &mut LinearVelocity), With<PlayerCamera>>,
+    mut player_tf_query: Query<
+        (&mut Transform, &mut LinearVelocity),
+        (With<PlayerCamera>, Without<Noclip>),
+    >,
     level_start: Res<LevelStartLocation>,
+    last_checkpoint: Res<LastCheckpoint>,
     input: Res<ButtonInput<KeyCode>>,
     mut commands: Commands,
     server: Res<AssetServer>,
 ) {
     for (mut player_tf, mut lin_vel) in &mut player_tf_query {
-        // reset player location to start transform
         // also do it if we're way oob )happens on wasm sometimes
         if input.pressed(KeyCode::KeyR) || player_tf.translation.y < MIN_Y {
-            commands.spawn(SamplePlayer::new(server.load(get_random_dead_sound_path())));
-            player_tf.translation = level_start.spawn.clone();
-            // also set the velocity to 0 so we don't clip through stuff on respawn
-            lin_vel.0 = Vec3::ZERO;
+            reset_player_to_start(
+                &mut commands,
+                &server,
+                &mut player_tf,
+                &mut lin_vel,
+                &last_checkpoint,
+                &level_start,
+            );
         }
     }
 }
 
+/// Kill the player: death noise, back to the last checkpoint (or start transform if none)
+fn reset_player_to_start(
+    commands: &mut Commands,
+    server: &AssetServer,
+    player_tf: &mut Transform,
+    lin_vel: &mut LinearVelocity,
+    last_checkpoint: &LastCheckpoint,
+    level_start: &LevelStartLocation,
+) {
+    commands.spawn(SamplePlayer::new(server.load(get_random_dead_sound_path())));
+    player_tf.translation = last_checkpoint.0.unwrap_or(level_start.spawn);
+    // also set the velocity to 0 so we don't clip through stuff on respawn
+    lin_vel.0 = Vec3::ZERO;
+}
+
 fn get_random_dead_sound_path() -> String {
     let mut rng = rand::rng();
     let noises = vec![
@@ -500,70 +1098,432 @@ fn get_random_dead_sound_path() -> String {
     noises.choose(&mut rng).unwrap().to_string()
 }
 
+#[allow(clippy::too_many_arguments)]
 fn update_player_start_location(
-    new_player_start: Query<(&PlayerStart, &Transform), Added<Transform>>,
+    new_player_start: Query<(Entity, &PlayerStart, &Transform), Added<Transform>>,
     mut level_start: ResMut<LevelStartLocation>,
+    mut target_start: ResMut<TargetStart>,
     mut next_state: ResMut<NextState<GameState>>,
+    mut fades: Query<&mut TransitionFade>,
+    level_tuning: Query<&LevelTuning>,
+    mut tuning: ResMut<MovementTuning>,
+    mut gravity: ResMut<Gravity>,
+    mut constraints: ResMut<CameraConstraints>,
 ) {
-    for (new_start, start_transform) in &new_player_start {
-        level_start.spawn = start_transform.translation;
-        level_start.bgm_name = new_start.bgm_name.clone();
-        level_start.bgm_vol = new_start.bgm_vol as f32;
-        level_start.bg_color = new_start.level_atmosphere_color;
+    // sorted so which start wins never depends on query order
+    let mut starts: Vec<_> = new_player_start.iter().collect();
+    if starts.is_empty() {
+        return;
+    }
+    starts.sort_by_key(|(entity, _, _)| *entity);
+    let mut seen_names = HashSet::new();
+    for (_, start, _) in &starts {
+        if !seen_names.insert(start.name.as_str()) {
+            warn!(
+                "more than one PlayerStart named '{}' in this level, using the first",
+                start.name
+            );
+        }
+    }
+
+    apply_level_tuning(
+        level_tuning.iter().next(),
+        &mut tuning,
+        &mut gravity,
+        &mut constraints,
+    );
+
+    // use the start the hole asked for, otherwise the unnamed one, otherwise whatever's first
+    let wanted = target_start.0.take();
+    let named = wanted
+        .as_ref()
+        .and_then(|name| starts.iter().find(|(_, start, _)| &start.name == name));
+    if let Some(name) = &wanted
+        && named.is_none()
+    {
+        warn!("no PlayerStart named '{name}' in this level, using the default one");
+    }
+    let Some((_, new_start, start_transform)) = named
+        .or_else(|| starts.iter().find(|(_, start, _)| start.name.is_empty()))
+        .or(starts.first())
+    else {
+        return;
+    };
+
+    level_start.spawn = start_transform.translation;
+    level_start.start_name = new_start.name.clone();
+    level_start.bgm_name = new_start.bgm_name.clone();
+    level_start.bgm_vol = new_start.bgm_vol as f32;
+    level_start.intro_name = if new_start.intro_name.is_empty() {
+        DEFAULT_INTRO_NAME.to_string()
+    } else {
+        new_start.intro_name.clone()
+    };
+    level_start.bg_color = new_start.level_atmosphere_color;
+
+    // Also set state to loaded (is this the right place to do this lol?)
+    next_state.set(GameState::InGame);
+    // and bring the lights back up if we came in through a hole
+    for mut fade in &mut fades {
+        fade.start_fade_out();
+    }
+}
 
-        // Also set state to loaded (is this the right place to do this lol?)
-        next_state.set(GameState::InGame);
+// how long a loaded level gets to show a PlayerStart before we give up on it
+const PLAYER_START_TIMEOUT_S: f32 = 3.0;
+
+#[derive(Resource)]
+struct PlayerStartTimeout(Timer);
+
+impl Default for PlayerStartTimeout {
+    fn default() -> Self {
+        PlayerStartTimeout(Timer::from_seconds(PLAYER_START_TIMEOUT_S, TimerMode::Once))
     }
 }
 
+// Movement and camera limits from the level's LevelTuning, levels without one get the
+// defaults back
+fn apply_level_tuning(
+    level_tuning: Option<&LevelTuning>,
+    tuning: &mut MovementTuning,
+    gravity: &mut Gravity,
+    constraints: &mut CameraConstraints,
+) {
+    *tuning = level_tuning
+        .map(|level| MovementTuning {
+            gravity: level.gravity,
+            player_speed: level.player_speed,
+            sprint_boost: level.sprint_boost,
+        })
+        .unwrap_or_default();
+    gravity.0 = Vec3::NEG_Y * tuning.gravity;
+    constraints.pitch_limit = level_tuning
+        .map(|level| {
+            level
+                .pitch_limit
+                .to_radians()
+                .clamp(0.0, DEFAULT_PITCH_LIMIT)
+        })
+        .unwrap_or(DEFAULT_PITCH_LIMIT);
+}
+
+fn reset_player_start_timeout(mut timeout: ResMut<PlayerStartTimeout>) {
+    timeout.0.reset();
+}
+
+// A map with no PlayerStart would leave us loading forever, so once it's in and still
+// hasn't shown one, drop the player at the default spot
+#[allow(clippy::too_many_arguments)]
+fn fall_back_to_default_start(
+    scenes: Query<&SceneRoot>,
+    server: Res<AssetServer>,
+    time: Res<Time>,
+    current_level: Res<CurrentLevel>,
+    mut timeout: ResMut<PlayerStartTimeout>,
+    mut level_start: ResMut<LevelStartLocation>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut fades: Query<&mut TransitionFade>,
+    level_tuning: Query<&LevelTuning>,
+    mut tuning: ResMut<MovementTuning>,
+    mut gravity: ResMut<Gravity>,
+    mut constraints: ResMut<CameraConstraints>,
+) {
+    // big maps can take a while, only start counting once it's actually loaded
+    if scenes.is_empty() || !scenes.iter().all(|scene| server.is_loaded(&scene.0)) {
+        return;
+    }
+    if !timeout.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    warn!(
+        "no PlayerStart in level '{}' after {PLAYER_START_TIMEOUT_S}s, using the default spot",
+        current_level.0
+    );
+    *level_start = LevelStartLocation::default();
+    // the level's tuning still counts without a start, and the last level's shouldn't
+    apply_level_tuning(
+        level_tuning.iter().next(),
+        &mut tuning,
+        &mut gravity,
+        &mut constraints,
+    );
+    next_state.set(GameState::InGame);
+    for mut fade in &mut fades {
+        fade.start_fade_out();
+    }
+}
+
+const MAX_SHAKE_ROLL: f32 = 0.08;
+const SHAKE_DECAY_PER_S: f32 = 2.5;
+const SHAKE_FREQ: f32 = 37.0;
+
+// Screen shake amount, 0 to 1. Bump it with `add_trauma` and it decays on its own
+#[derive(Resource, Default)]
+struct CameraShake {
+    trauma: f32,
+}
+
+impl CameraShake {
+    fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+}
+
+// Shake by rolling the camera. The look controller keeps whatever roll is there and the
+// body has its rotation locked, so this doesn't fight mouse look or physics
+fn apply_camera_shake(
+    mut shake: ResMut<CameraShake>,
+    settings: Res<Persistent<GameSettings>>,
+    time: Res<Time>,
+    mut camera: Query<&mut Transform, With<PlayerCamera>>,
+) {
+    shake.trauma = (shake.trauma - SHAKE_DECAY_PER_S * time.delta_secs()).max(0.0);
+    let Ok(mut transform) = camera.single_mut() else {
+        return;
+    };
+    let (yaw, pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
+    let t = time.elapsed_secs() * SHAKE_FREQ;
+    // couple of out of phase sines reads as noise at this speed
+    let noise = (t.sin() + (t * 1.7 + 1.3).sin()) * 0.5;
+    let target_roll = if settings.motion_enabled() {
+        MAX_SHAKE_ROLL * shake.trauma * shake.trauma * noise
+    } else {
+        0.0
+    };
+    if target_roll != roll {
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, target_roll);
+    }
+}
+
+const SPRINT_FOV_BOOST_DEG: f32 = 8.0;
+const FOV_LERP_SPEED: f32 = 8.0;
+
+// Widen the view a little while sprinting
+fn update_sprint_fov(
+    sprinting: Res<Sprinting>,
+    settings: Res<Persistent<GameSettings>>,
+    time: Res<Time>,
+    mut projections: Query<&mut Projection, With<PlayerCamera>>,
+) {
+    let base_fov = settings.fov().to_radians();
+    let boosted_fov = (settings.fov() + SPRINT_FOV_BOOST_DEG).to_radians();
+    let target_fov = if sprinting.0 && settings.motion_enabled() {
+        boosted_fov
+    } else {
+        base_fov
+    };
+    let t = (time.delta_secs() * FOV_LERP_SPEED).min(1.0);
+    for mut projection in &mut projections {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = perspective
+                .fov
+                .lerp(target_fov, t)
+                .clamp(base_fov, boosted_fov);
+        }
+    }
+}
+
+// just shy of straight up/down, so the view never flips over
+const DEFAULT_PITCH_LIMIT: f32 = FRAC_PI_2 - 0.01;
+
+/// Limits on where the camera can point, from the level's LevelTuning if it has one
+#[derive(Resource)]
+struct CameraConstraints {
+    // radians either side of level
+    pitch_limit: f32,
+}
+
+impl Default for CameraConstraints {
+    fn default() -> Self {
+        CameraConstraints {
+            pitch_limit: DEFAULT_PITCH_LIMIT,
+        }
+    }
+}
+
+const MOUSE_SENSITIVITY: f32 = 0.003;
+// extra sensitivity per pixel of motion in a frame when acceleration is on, and its cap
+const MOUSE_ACCEL_GAIN: f32 = 0.02;
+const MAX_MOUSE_ACCEL: f32 = 2.5;
+
 fn update_camera_transform(
     accumulated_mouse_motion: Res<AccumulatedMouseMotion>,
     cursor_options: Single<&CursorOptions>,
+    settings: Res<Persistent<GameSettings>>,
+    look: Res<LookEnabled>,
+    constraints: Res<CameraConstraints>,
     mut camera: Query<&mut Transform, With<PlayerCamera>>,
+    mut smoothed_delta: Local<Vec2>,
 ) {
     let Ok(mut transform) = camera.single_mut() else {
         return;
     };
 
-    let delta = if cursor_options.grab_mode == CursorGrabMode::Locked {
+    // motion piled up while the cursor was free would snap the view on regrab, drop it
+    if look.is_changed() && look.0 {
+        *smoothed_delta = Vec2::ZERO;
+        return;
+    }
+    let raw_delta = if look.0 && cursor_options.grab_mode == CursorGrabMode::Locked {
         accumulated_mouse_motion.delta
     } else {
         Vec2::ZERO
     };
-    let delta_yaw = -delta.x * 0.003;
-    let delta_pitch = -delta.y * 0.003;
+    // low-pass the look motion, smoothing of 0 passes it straight through
+    *smoothed_delta = smoothed_delta.lerp(raw_delta, 1.0 - settings.mouse_smoothing());
+    let mut delta = *smoothed_delta;
+    if settings.mouse_accel_enabled() {
+        delta *= (1.0 + delta.length() * MOUSE_ACCEL_GAIN).min(MAX_MOUSE_ACCEL);
+    }
+    let delta_yaw = -delta.x * MOUSE_SENSITIVITY;
+    let delta_pitch = -delta.y * MOUSE_SENSITIVITY;
 
     let (yaw, pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
     let yaw = yaw + delta_yaw;
 
-    const PITCH_LIMIT: f32 = FRAC_PI_2 - 0.01;
-    let pitch = (pitch + delta_pitch).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+    let pitch_limit = constraints.pitch_limit;
+    let pitch = (pitch + delta_pitch).clamp(-pitch_limit, pitch_limit);
 
     transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
 }
 
-fn capture_cursor(mut cursor: Single<&mut CursorOptions>) {
+/// Whether mouse motion turns the camera, follows the cursor grab
+#[derive(Resource, Default, PartialEq)]
+struct LookEnabled(bool);
+
+fn not_in_dialogue(dialogue: Res<DialogueState>) -> bool {
+    !dialogue.is_active()
+}
+
+fn capture_cursor(mut cursor: Single<&mut CursorOptions>, mut look: ResMut<LookEnabled>) {
     cursor.visible = false;
     cursor.grab_mode = CursorGrabMode::Locked;
+    // only flag a change on an actual regrab, not every click while already grabbed
+    look.set_if_neq(LookEnabled(true));
 }
 
-fn release_cursor(mut cursor: Single<&mut CursorOptions>) {
+fn release_cursor(mut cursor: Single<&mut CursorOptions>, mut look: ResMut<LookEnabled>) {
     cursor.visible = true;
     cursor.grab_mode = CursorGrabMode::None;
+    look.set_if_neq(LookEnabled(false));
 }
 
+// alt-tabbing (or the browser dropping pointer lock) frees the cursor, click to grab it again
+fn release_cursor_on_unfocus(
+    mut focus_events: MessageReader<WindowFocused>,
+    cursor: Single<&mut CursorOptions>,
+    look: ResMut<LookEnabled>,
+) {
+    if focus_events.read().any(|event| !event.focused) {
+        release_cursor(cursor, look);
+    }
+}
+
+// map file the player is currently in (relative to assets/maps)
+#[derive(Resource, Clone, Debug)]
+struct CurrentLevel(pub String);
+
 // Plugin that loads trenchbroom map
 struct TrenchLoaderPlugin;
 impl Plugin for TrenchLoaderPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, spawn_initial_map);
+        app.insert_resource(CurrentLevel(INITIAL_LEVEL.to_string()))
+            .add_systems(
+                OnTransition {
+                    exited: GameState::Title,
+                    entered: GameState::Loading,
+                },
+                spawn_initial_map,
+            );
     }
 }
 
 const INITIAL_LEVEL: &'static str = "start.map";
 
-fn spawn_initial_map(mut commands: Commands, asset_server: Res<AssetServer>) {
+// the title screen picks the level, INITIAL_LEVEL for a new game or the saved one to continue
+fn spawn_initial_map(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    current_level: Res<CurrentLevel>,
+) {
     commands.spawn(SceneRoot(
-        asset_server.load(format!("maps/{INITIAL_LEVEL}#Scene")),
+        asset_server.load(format!("maps/{}#Scene", current_level.0)),
     ));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Speed after holding full input from a standstill for `secs` of `hz` fixed ticks
+    fn speed_after(hz: f32, secs: f32) -> f32 {
+        let dt = 1.0 / hz;
+        let target = Vec2::X * PLAYER_SPEED;
+        let mut vel = Vec2::ZERO;
+        for _ in 0..(secs * hz).round() as usize {
+            vel = ease_toward(vel, target, PLAYER_ACCEL, dt);
+        }
+        vel.length()
+    }
+
+    #[test]
+    fn terminal_velocity_is_the_same_at_30hz_and_60hz() {
+        let speed_30 = speed_after(30.0, 2.0);
+        let speed_60 = speed_after(60.0, 2.0);
+        assert!(
+            (speed_30 - PLAYER_SPEED).abs() < 1e-3,
+            "30hz topped out at {speed_30}"
+        );
+        assert!(
+            (speed_30 - speed_60).abs() < 1e-3,
+            "{speed_30} vs {speed_60}"
+        );
+        // and it's on the same curve the whole way there, not just at the end
+        assert!((speed_after(30.0, 0.1) - speed_after(60.0, 0.1)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ease_toward_splits_ticks_evenly() {
+        let (current, target) = (Vec2::new(1.0, -2.0), Vec2::new(3.5, 3.0));
+        let one = ease_toward(current, target, PLAYER_ACCEL, 1.0 / 30.0);
+        let half = ease_toward(current, target, PLAYER_ACCEL, 1.0 / 60.0);
+        let two = ease_toward(half, target, PLAYER_ACCEL, 1.0 / 60.0);
+        assert!(one.distance(two) < 1e-5, "{one} vs {two}");
+    }
+
+    #[test]
+    fn levels_without_tuning_get_the_defaults_back() {
+        let mut tuning = MovementTuning::default();
+        let mut gravity = Gravity(Vec3::NEG_Y * GRAVITY_MULT);
+        let mut constraints = CameraConstraints::default();
+        let floaty = LevelTuning {
+            gravity: 2.0,
+            player_speed: 12.0,
+            pitch_limit: 30.0,
+            ..default()
+        };
+        apply_level_tuning(Some(&floaty), &mut tuning, &mut gravity, &mut constraints);
+        assert_eq!(tuning.player_speed, 12.0);
+        assert_eq!(gravity.0, Vec3::NEG_Y * 2.0);
+        assert_eq!(constraints.pitch_limit, 30f32.to_radians());
+
+        apply_level_tuning(None, &mut tuning, &mut gravity, &mut constraints);
+        assert_eq!(tuning.player_speed, PLAYER_SPEED);
+        assert_eq!(tuning.sprint_boost, PLAYER_SPRINT_BOOST);
+        assert_eq!(gravity.0, Vec3::NEG_Y * GRAVITY_MULT);
+        assert_eq!(constraints.pitch_limit, DEFAULT_PITCH_LIMIT);
+    }
+
+    #[test]
+    fn footstep_cadence_spans_walking_to_sprinting() {
+        let walking = get_walking_cadence_secs(PLAYER_SPEED);
+        let sprinting = get_walking_cadence_secs(PLAYER_SPEED * PLAYER_SPRINT_BOOST);
+        let crouching = get_walking_cadence_secs(PLAYER_CROUCH_SPEED);
+        assert_eq!(walking, WALKING_CADENCE_SLOW_S);
+        assert_eq!(crouching, WALKING_CADENCE_SLOW_S);
+        assert!(
+            sprinting - WALKING_CADENCE_FAST_S < 0.05,
+            "sprinting steps every {sprinting}s"
+        );
+    }
+}
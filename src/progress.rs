@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+use bevy_persistent::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    CurrentLevel, LevelStartLocation,
+    ui::{GameSettings, GameState, settings_dir},
+};
+
+// Player progress, saved alongside settings
+#[derive(Default, Resource, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct Progress {
+    pub last_level: String,
+    // PlayerStart the player came in at in last_level, empty for the default one
+    pub last_start: String,
+    pub visited_levels: BTreeSet<String>,
+    // most coins grabbed in one visit, by level
+    pub best_coins: BTreeMap<String, u32>,
+}
+
+// Plugin that keeps progress on disk, saving once per level transition and on exit
+pub struct ProgressPlugin;
+impl Plugin for ProgressPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(
+            Persistent::<Progress>::builder()
+                .name("progress")
+                .format(StorageFormat::Toml)
+                .path(settings_dir().join("progress.toml"))
+                .default(Progress::default())
+                .build()
+                .expect("failed to initialize progress"),
+        )
+        .add_systems(
+            OnTransition {
+                exited: GameState::Loading,
+                entered: GameState::InGame,
+            },
+            record_level_progress,
+        )
+        .add_systems(Last, save_progress_on_exit);
+    }
+}
+
+fn record_level_progress(
+    current_level: Res<CurrentLevel>,
+    level_start: Res<LevelStartLocation>,
+    settings: Res<Persistent<GameSettings>>,
+    mut progress: ResMut<Persistent<Progress>>,
+) {
+    progress.last_level = current_level.0.clone();
+    progress.last_start = level_start.start_name.clone();
+    progress.visited_levels.insert(current_level.0.clone());
+    autosave(&settings, &progress);
+}
+
+fn save_progress_on_exit(
+    mut exits: MessageReader<AppExit>,
+    settings: Res<Persistent<GameSettings>>,
+    progress: Res<Persistent<Progress>>,
+) {
+    if exits.read().next().is_some() {
+        autosave(&settings, &progress);
+    }
+}
+
+// Writes progress to disk if the player hasn't turned autosave off. Failures get
+// logged, a bad write shouldn't take the game down with it
+fn autosave(settings: &GameSettings, progress: &Persistent<Progress>) {
+    if !settings.autosave_enabled() {
+        return;
+    }
+    if let Err(e) = progress.persist() {
+        error!("Failed to autosave progress: {}", e);
+    }
+}
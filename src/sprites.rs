@@ -1,35 +1,57 @@
 use crate::{
-    LevelStuff, PlayerCamera, TextBox,
-    fonts::SERIF_FONT_PATH,
+    CameraShake, CurrentLevel, DEFAULT_PITCH_LIMIT, FadeOut, GRAVITY_MULT, GameLayer, Grounded,
+    LevelBgm, LevelStartLocation, LevelStuff, Noclip, PLAYER_SPEED, PLAYER_SPRINT_BOOST,
+    PlayerCamera, TextBox,
+    fonts::{SANS_FONT_PATH, SERIF_FONT_PATH},
     get_scalar_boosted_rand_sfx_speed,
-    text_parse::parse_random_text,
-    ui::{GameState, TEXT_COLOR},
+    progress::Progress,
+    reset_player_to_start,
+    text_parse::{
+        DialogueLibrary, DialogueTemplates, TemplateCache, TextContext, parse_random_text_with,
+        validate_random_text,
+    },
+    ui::{
+        GameSettings, GameState, TEXT_COLOR, TransitionFade, interact_key_just_pressed,
+        transition_fade,
+    },
 };
 use avian3d::prelude::*;
 use bevy::{
-    ecs::{lifecycle::HookContext, world::DeferredWorld},
+    asset::LoadState,
+    ecs::{lifecycle::HookContext, system::SystemParam, world::DeferredWorld},
     input::common_conditions::input_just_pressed,
     prelude::*,
     scene::SceneInstance,
 };
+use bevy_persistent::prelude::*;
 use bevy_seedling::prelude::*;
 use bevy_trenchbroom::prelude::*;
+use rand::{Rng, seq::IndexedRandom};
+use std::collections::{HashMap, HashSet};
+
+pub const DEFAULT_INTRO_NAME: &str = "intro1";
 
 // not visible to player in game, used for marking player start loc in level
 #[point_class(
     model({ path: "sprites/start.png", scale: 0.5 }),
 )]
 pub struct PlayerStart {
+    // holes can aim for a specific start by name, unnamed is the default
+    pub name: String,
     pub level_atmosphere_color: Color,
     pub bgm_name: String,
     pub bgm_vol: i32,
+    // stinger played on arrival before the bgm kicks in
+    pub intro_name: String,
 }
 impl Default for PlayerStart {
     fn default() -> Self {
         PlayerStart {
+            name: String::new(),
             level_atmosphere_color: Color::WHITE,
             bgm_name: "bgm1".to_string(),
             bgm_vol: 100,
+            intro_name: DEFAULT_INTRO_NAME.to_string(),
         }
     }
 }
@@ -39,45 +61,206 @@ impl Default for PlayerStart {
 )]
 #[component(on_add = Self::on_add)]
 struct NPCSprite {
+    pub size: f32,
+    // how close the player has to be to interact
+    pub interact_range: f32,
     pub selectable: bool,
     pub text: Option<String>,
     pub name: String,
     pub voice_line: String,
+    // render this one's dialogue all wobbly
+    pub cursed: bool,
+    // random text template replacing the usual TALK prompt
+    pub action_prompt: Option<String>,
+    // roams around its spawn when above 0
+    pub wander_speed: f32,
+    // how far from its spawn it'll roam
+    pub wander_radius: f32,
+    // tags along behind the player instead of wandering
+    pub follow_player: bool,
 }
 impl Default for NPCSprite {
     fn default() -> Self {
         NPCSprite {
+            size: DEFAULT_SMALL_SPRITE_SIZE,
+            interact_range: MAX_DIST_FOR_FOCUS,
             selectable: true,
             text: None,
             name: "".to_string(),
             voice_line: "voice1_whiny".to_string(),
+            cursed: false,
+            action_prompt: None,
+            wander_speed: 0.0,
+            wander_radius: DEFAULT_WANDER_RADIUS,
+            follow_player: false,
         }
     }
 }
 
 #[derive(Component, Clone, PartialEq)]
 pub struct FocusDetails {
+    // the sprite these details live on, for reaching back to its transform etc.
+    pub entity: Entity,
+    pub interact_range: f32,
     pub name: String,
     pub focus_type: FocusType,
     pub selectable: bool,
     pub text: Option<String>,
     pub sound_on_action: Option<String>,
+    // dialogue gets the jittery treatment
+    pub cursed: bool,
+    // random text template for the action prompt, None for the default one
+    pub action_prompt: Option<String>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum FocusType {
     NPC,
     Hole,
+    Door,
+    Lever,
+    Coin,
+    // looked at but never interacted with
+    Decoration,
+}
+
+impl FocusType {
+    /// Whether focusing this shows an action prompt and clicking it does anything
+    pub fn is_interactive(self) -> bool {
+        matches!(
+            self,
+            FocusType::NPC | FocusType::Hole | FocusType::Door | FocusType::Lever
+        )
+    }
+}
+
+/// Filter for `list_focusables`, a `None` field matches anything
+#[derive(Clone, Copy, Default)]
+pub struct FocusFilter {
+    pub focus_type: Option<FocusType>,
+    pub selectable: Option<bool>,
+}
+
+/// All focusable entities in the loaded level that pass `filter`
+pub fn list_focusables(
+    focusables: &Query<(Entity, &FocusDetails)>,
+    filter: FocusFilter,
+) -> Vec<Entity> {
+    focusables
+        .iter()
+        .filter(|(_, details)| {
+            filter
+                .focus_type
+                .is_none_or(|focus_type| details.focus_type == focus_type)
+                && filter
+                    .selectable
+                    .is_none_or(|selectable| details.selectable == selectable)
+        })
+        .map(|(ent, _)| ent)
+        .collect()
+}
+
+// default world sizes for sprites, maps can override per entity
+const DEFAULT_SMALL_SPRITE_SIZE: f32 = 0.42;
+const DEFAULT_LARGE_SPRITE_SIZE: f32 = 1.414;
+const DEFAULT_COIN_SPRITE_SIZE: f32 = 0.1;
+
+/// Meshes and materials shared by every sprite of a kind, so dense maps
+/// don't make a fresh set per entity
+#[derive(Resource)]
+pub struct SpriteAssets {
+    // quads keyed by the bits of their f32 size
+    quads: HashMap<u32, Handle<Mesh>>,
+    pub rat: SpriteMaterials,
+    pub hole: SpriteMaterials,
+    pub plant: Handle<StandardMaterial>,
+    pub face: SpriteMaterials,
+    pub coin: Handle<StandardMaterial>,
+    pub lever: SpriteMaterials,
+    pub lever_on: SpriteMaterials,
+}
+
+/// A sprite's material plus a brighter copy of it to show while focused
+#[derive(Clone)]
+pub struct SpriteMaterials {
+    pub normal: Handle<StandardMaterial>,
+    pub highlight: Handle<StandardMaterial>,
+}
+
+// emissive strength of focused sprites, normal ones are 1.0
+const HIGHLIGHT_EMISSIVE: f32 = 2.5;
+
+impl FromWorld for SpriteAssets {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        SpriteAssets {
+            quads: HashMap::new(),
+            rat: sprite_materials(asset_server, "sprites/rat.png"),
+            hole: sprite_materials(asset_server, "sprites/hole.png"),
+            plant: sprite_material(asset_server, "sprites/plant.png", 1.0),
+            face: sprite_materials(asset_server, "sprites/face.png"),
+            coin: sprite_material(asset_server, "sprites/coin.png", 1.0),
+            // no lever art yet, borrowing these
+            lever: sprite_materials(asset_server, "sprites/start.png"),
+            lever_on: sprite_materials(asset_server, "sprites/coin.png"),
+        }
+    }
+}
+
+impl SpriteAssets {
+    /// Square quad `size` units across, made on first use and shared after that
+    pub fn quad(&mut self, asset_server: &AssetServer, size: f32) -> Handle<Mesh> {
+        self.quads
+            .entry(size.to_bits())
+            .or_insert_with(|| asset_server.add(Mesh::from(Rectangle::new(size, size))))
+            .clone()
+    }
+}
+
+/// Shared quad mesh for a sprite of the given size
+fn sprite_mesh(world: &mut DeferredWorld, size: f32) -> Handle<Mesh> {
+    let asset_server = world.resource::<AssetServer>().clone();
+    world
+        .resource_mut::<SpriteAssets>()
+        .quad(&asset_server, size)
+}
+
+/// Sprites and trigger volumes only ever touch the player, never level geometry or each other
+fn sprite_collision_layers() -> CollisionLayers {
+    CollisionLayers::new(GameLayer::Sprite, [GameLayer::Player])
+}
+
+/// Collider for picking/overlaps, never smaller than a unit cube so tiny sprites stay easy to hit
+fn sprite_collider(size: f32) -> Collider {
+    Collider::from(Cuboid::from_length(size.max(1.0)))
+}
+
+fn sprite_materials(asset_server: &AssetServer, path: &'static str) -> SpriteMaterials {
+    SpriteMaterials {
+        normal: sprite_material(asset_server, path, 1.0),
+        highlight: sprite_material(asset_server, path, HIGHLIGHT_EMISSIVE),
+    }
 }
 
-enum Selection {
-    On,
-    Off,
+fn sprite_material(
+    asset_server: &AssetServer,
+    path: &'static str,
+    emissive_strength: f32,
+) -> Handle<StandardMaterial> {
+    asset_server.add(StandardMaterial {
+        base_color_texture: Some(asset_server.load(path)),
+        emissive: LinearRgba::rgb(emissive_strength, emissive_strength, emissive_strength),
+        emissive_texture: Some(asset_server.load(path)),
+        perceptual_roughness: 1.0,
+        alpha_mode: AlphaMode::Mask(1.0),
+        cull_mode: None,
+        ..default()
+    })
 }
 
 impl NPCSprite {
     pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
-        let Some(asset_server) = world.get_resource::<AssetServer>() else {
+        let Some(sprite_assets) = world.get_resource::<SpriteAssets>() else {
             return;
         };
         // Get the selectable value from the NPCSprite
@@ -87,6 +270,24 @@ impl NPCSprite {
         let selectable = npc_sprite.selectable;
         let name = npc_sprite.name.clone();
         let voice_line = npc_sprite.voice_line.clone();
+        let size = npc_sprite.size;
+        let interact_range = npc_sprite.interact_range;
+        let cursed = npc_sprite.cursed;
+        let action_prompt = npc_sprite.action_prompt.clone();
+        let follow_player = npc_sprite.follow_player;
+        let wander = (npc_sprite.wander_speed > 0.0 && !follow_player)
+            .then(|| Wander::new(npc_sprite.wander_speed, npc_sprite.wander_radius));
+        // flag typos in authored dialogue, runtime parsing is lenient so it'll still show
+        if let Some(text) = &npc_sprite.text
+            && let Err(e) = validate_random_text(text)
+        {
+            warn!("NPC '{name}' has malformed dialogue text: {e}");
+        }
+        if let Some(prompt) = &action_prompt
+            && let Err(e) = validate_random_text(prompt)
+        {
+            warn!("NPC '{name}' has a malformed action prompt: {e}");
+        }
         let text = if !selectable {
             None
         } else {
@@ -98,53 +299,178 @@ impl NPCSprite {
             )
         };
 
-        let rect_mesh = asset_server.add(Mesh::from(Rectangle::new(0.42, 0.42)));
-        let material = asset_server.add(StandardMaterial {
-            base_color_texture: Some(asset_server.load("sprites/rat.png")),
-            emissive: Color::WHITE.into(),
-            emissive_texture: Some(asset_server.load("sprites/rat.png")),
-            perceptual_roughness: 1.0,
+        let materials = sprite_assets.rat.clone();
+        let rect_mesh = sprite_mesh(&mut world, size);
+        world.commands().entity(ctx.entity).insert((
+            Mesh3d(rect_mesh),
+            Billboard,
+            MeshMaterial3d(materials.normal.clone()),
+            RigidBody::Static,
+            Sensor,
+            sprite_collision_layers(),
+            sprite_collider(size),
+            FocusDetails {
+                entity: ctx.entity,
+                interact_range,
+                name,
+                selectable,
+                text,
+                sound_on_action: Some(voice_line),
+                focus_type: FocusType::NPC,
+                cursed,
+                action_prompt,
+            },
+            // for muttering at the player when they bump into it
+            CollisionEventsEnabled,
+            CollidingEntities::default(),
+            PlayerInside::default(),
+            GreetingCooldown::default(),
+            HighlightMaterials(materials.clone()),
+            LevelStuff,
+        ));
+        // roamers get moved by velocity, so they can't be static
+        if let Some(wander) = wander {
+            world
+                .commands()
+                .entity(ctx.entity)
+                .insert((RigidBody::Kinematic, wander));
+        }
+        if follow_player {
+            world
+                .commands()
+                .entity(ctx.entity)
+                .insert((RigidBody::Kinematic, FollowNPC::default()));
+        }
+    }
+}
 
-            alpha_mode: AlphaMode::Mask(1.0),
-            cull_mode: None,
-            ..default()
-        });
-        let hover_material = asset_server.add(StandardMaterial {
-            base_color_texture: Some(asset_server.load("sprites/rat2.png")),
-            emissive: Color::WHITE.into(),
-            emissive_texture: Some(asset_server.load("sprites/rat2.png")),
-            perceptual_roughness: 1.0,
-            alpha_mode: AlphaMode::Mask(1.0),
-            cull_mode: None,
-            ..default()
-        });
-        world
-            .commands()
-            .entity(ctx.entity)
-            .insert((
-                Mesh3d(rect_mesh),
-                MeshMaterial3d(material.clone()),
-                PhysicsPickable,
-                RigidBody::Static,
-                Sensor,
-                Collider::from(Cuboid::default()),
-                FocusDetails {
-                    name,
-                    selectable,
-                    text,
-                    sound_on_action: Some(voice_line),
-                    focus_type: FocusType::NPC,
-                },
-                LevelStuff,
-            ))
-            .observe(update_material_on::<Pointer<Over>>(
-                hover_material.clone(),
-                Selection::On,
-            ))
-            .observe(update_material_on::<Pointer<Out>>(
-                material.clone(),
-                Selection::Off,
-            ));
+const DEFAULT_WANDER_RADIUS: f32 = 3.0;
+// how close counts as having arrived at a wander point
+const WANDER_ARRIVE_DIST: f32 = 0.2;
+// seconds an NPC hangs around between wander points
+const WANDER_REST_S: std::ops::Range<f32> = 1.0..4.0;
+
+/// Roams between random points within `radius` of where the NPC spawned
+#[derive(Component)]
+struct Wander {
+    speed: f32,
+    radius: f32,
+    // picked up on the first update, the transform isn't final when the sprite is added
+    home: Option<Vec3>,
+    target: Option<Vec3>,
+    rest: Timer,
+}
+
+impl Wander {
+    fn new(speed: f32, radius: f32) -> Self {
+        Wander {
+            speed,
+            radius,
+            home: None,
+            target: None,
+            rest: Timer::from_seconds(0.0, TimerMode::Once),
+        }
+    }
+}
+
+fn wander_npcs(
+    mut npcs: Query<(Entity, &mut Wander, &mut Transform, &mut LinearVelocity)>,
+    dialogue: Res<DialogueState>,
+    time: Res<Time>,
+) {
+    let mut rng = rand::rng();
+    for (entity, mut wander, mut transform, mut lin_vel) in &mut npcs {
+        // hold still while the player's talking to us
+        if dialogue.speaker_entity == Some(entity) {
+            lin_vel.0 = Vec3::ZERO;
+            continue;
+        }
+        let home = *wander.home.get_or_insert(transform.translation);
+        let Some(target) = wander.target else {
+            // resting, then pick somewhere new on the ground plane
+            if wander.rest.tick(time.delta()).is_finished() {
+                let offset = Vec2::from_angle(rng.random_range(0.0..std::f32::consts::TAU))
+                    * rng.random_range(0.0..=wander.radius);
+                wander.target = Some(home + Vec3::new(offset.x, 0.0, offset.y));
+            }
+            lin_vel.0 = Vec3::ZERO;
+            continue;
+        };
+
+        let to_target = (target - transform.translation).with_y(0.0);
+        if to_target.length() <= WANDER_ARRIVE_DIST {
+            wander.target = None;
+            wander.rest = Timer::from_seconds(rng.random_range(WANDER_REST_S), TimerMode::Once);
+            lin_vel.0 = Vec3::ZERO;
+            continue;
+        }
+        lin_vel.0 = to_target.normalize() * wander.speed;
+        face_travel_direction(&mut transform, lin_vel.0);
+    }
+}
+
+/// Billboarding owns the rotation, so face the way we're walking by mirroring instead
+fn face_travel_direction(transform: &mut Transform, velocity: Vec3) {
+    let facing = if velocity.dot(*transform.right()) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    transform.scale.x = transform.scale.x.abs() * facing;
+}
+
+const FOLLOW_SPEED: f32 = 4.0;
+// stops this close to the player
+const FOLLOW_STOP_DIST: f32 = 2.0;
+// and doesn't start back up until the player gets this far away
+const FOLLOW_RESUME_DIST: f32 = 3.5;
+
+/// Steers straight toward the player, hanging back a little once it's caught up
+#[derive(Component)]
+pub struct FollowNPC {
+    pub speed: f32,
+    pub stop_distance: f32,
+    pub resume_distance: f32,
+    following: bool,
+}
+
+impl Default for FollowNPC {
+    fn default() -> Self {
+        FollowNPC {
+            speed: FOLLOW_SPEED,
+            stop_distance: FOLLOW_STOP_DIST,
+            resume_distance: FOLLOW_RESUME_DIST,
+            following: false,
+        }
+    }
+}
+
+fn follow_player(
+    mut followers: Query<
+        (&mut FollowNPC, &mut Transform, &mut LinearVelocity),
+        Without<PlayerCamera>,
+    >,
+    player: Single<&Transform, With<PlayerCamera>>,
+) {
+    for (mut follow, mut transform, mut lin_vel) in &mut followers {
+        let to_player = (player.translation - transform.translation).with_y(0.0);
+        let dist = to_player.length();
+        // gap between stop and resume so it doesn't stutter at the edge
+        if dist <= follow.stop_distance {
+            follow.following = false;
+        } else if dist >= follow.resume_distance {
+            follow.following = true;
+        }
+        if !follow.following {
+            lin_vel.0 = Vec3::ZERO;
+            continue;
+        }
+        // ease off as it closes in instead of slamming to a halt
+        let speed = follow
+            .speed
+            .min((dist - follow.stop_distance) * follow.speed);
+        lin_vel.0 = to_player.normalize_or_zero() * speed;
+        face_travel_direction(&mut transform, lin_vel.0);
     }
 }
 
@@ -154,304 +480,1429 @@ impl NPCSprite {
 #[component(on_add = Self::on_add)]
 struct HoleSprite {
     pub hole_target: String,
+    // name of the PlayerStart to come out at, empty for the default one
+    pub target_start: String,
+    pub size: f32,
+    // how close the player has to be to interact
+    pub interact_range: f32,
 }
 impl Default for HoleSprite {
     fn default() -> Self {
         HoleSprite {
             hole_target: String::new(),
+            target_start: String::new(),
+            size: DEFAULT_SMALL_SPRITE_SIZE,
+            interact_range: MAX_DIST_FOR_FOCUS,
         }
     }
 }
 
 impl HoleSprite {
     pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
-        let Some(asset_server) = world.get_resource::<AssetServer>() else {
+        let Some(sprite_assets) = world.get_resource::<SpriteAssets>() else {
             return;
         };
 
         let hole_sprite = world.get::<HoleSprite>(ctx.entity).unwrap();
         let hole_target = hole_sprite.hole_target.clone();
+        let size = hole_sprite.size;
+        let interact_range = hole_sprite.interact_range;
 
-        let rect_mesh = asset_server.add(Mesh::from(Rectangle::new(0.42, 0.42)));
-        let material = asset_server.add(StandardMaterial {
-            base_color_texture: Some(asset_server.load("sprites/hole.png")),
-            emissive_texture: Some(asset_server.load("sprites/hole.png")),
-            emissive: Color::WHITE.into(),
-            perceptual_roughness: 1.0,
-            alpha_mode: AlphaMode::Mask(1.0),
-            cull_mode: None,
-            ..default()
-        });
-        let hover_material = asset_server.add(StandardMaterial {
-            base_color_texture: Some(asset_server.load("sprites/hole.png")),
-            emissive_texture: Some(asset_server.load("sprites/hole.png")),
-            emissive: Color::WHITE.into(),
-            perceptual_roughness: 1.0,
-            alpha_mode: AlphaMode::Mask(1.0),
-            cull_mode: None,
-            ..default()
-        });
-        world
-            .commands()
-            .entity(ctx.entity)
-            .insert((
-                Mesh3d(rect_mesh),
-                MeshMaterial3d(material.clone()),
-                PhysicsPickable,
-                RigidBody::Static,
-                Sensor,
-                Collider::from(Cuboid::default()),
-                FocusDetails {
-                    name: "hole".to_string(),
-                    selectable: true,
-                    text: Some(hole_target),
-                    sound_on_action: Some("warp".to_string()),
-                    focus_type: FocusType::Hole,
-                },
-                LevelStuff,
-            ))
-            .observe(update_material_on::<Pointer<Over>>(
-                hover_material.clone(),
-                Selection::On,
-            ))
-            .observe(update_material_on::<Pointer<Out>>(
-                material.clone(),
-                Selection::Off,
-            ));
+        let materials = sprite_assets.hole.clone();
+        let rect_mesh = sprite_mesh(&mut world, size);
+        world.commands().entity(ctx.entity).insert((
+            Mesh3d(rect_mesh),
+            Billboard,
+            MeshMaterial3d(materials.normal.clone()),
+            RigidBody::Static,
+            Sensor,
+            sprite_collision_layers(),
+            sprite_collider(size),
+            // holes lie flat on the floor, so they need to tilt with the camera
+            BillboardMode::Full,
+            FocusDetails {
+                entity: ctx.entity,
+                interact_range,
+                name: "hole".to_string(),
+                selectable: true,
+                text: Some(hole_target),
+                sound_on_action: Some("warp".to_string()),
+                focus_type: FocusType::Hole,
+                cursed: false,
+                action_prompt: None,
+            },
+            HighlightMaterials(materials.clone()),
+            LevelStuff,
+        ));
     }
 }
 
+// Like a hole, but only lets you through if you're carrying the right key
+// (no door art yet so it borrows the hole sprite)
 #[point_class(
-    model({ path: "sprites/plant.png", scale: 2. }),
+    model({ path: "sprites/hole.png", scale: 0.5 }),
 )]
 #[component(on_add = Self::on_add)]
-struct PlantSprite {
-    pub name: String,
+struct DoorSprite {
+    // name of the KeyItemSprite that unlocks it
+    pub key: String,
+    pub target: String,
+    // name of the PlayerStart to come out at, empty for the default one
+    pub target_start: String,
+    pub size: f32,
+    // how close the player has to be to interact
+    pub interact_range: f32,
 }
-impl Default for PlantSprite {
+impl Default for DoorSprite {
     fn default() -> Self {
-        PlantSprite {
-            name: String::new(),
+        DoorSprite {
+            key: String::new(),
+            target: String::new(),
+            target_start: String::new(),
+            size: DEFAULT_SMALL_SPRITE_SIZE,
+            interact_range: MAX_DIST_FOR_FOCUS,
         }
     }
 }
 
-impl PlantSprite {
+impl DoorSprite {
     pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
-        let Some(asset_server) = world.get_resource::<AssetServer>() else {
+        let Some(sprite_assets) = world.get_resource::<SpriteAssets>() else {
             return;
         };
 
-        let plant_sprite = world.get::<PlantSprite>(ctx.entity).unwrap();
-        let plant_name = plant_sprite.name.clone();
+        let door_sprite = world.get::<DoorSprite>(ctx.entity).unwrap();
+        let target = door_sprite.target.clone();
+        let size = door_sprite.size;
+        let interact_range = door_sprite.interact_range;
 
-        let rect_mesh = asset_server.add(Mesh::from(Rectangle::new(1.414, 1.414)));
-        let material = asset_server.add(StandardMaterial {
-            base_color_texture: Some(asset_server.load("sprites/plant.png")),
-            emissive_texture: Some(asset_server.load("sprites/plant.png")),
-            emissive: Color::WHITE.into(),
-            perceptual_roughness: 1.0,
-            alpha_mode: AlphaMode::Mask(1.0),
-            cull_mode: None,
-            ..default()
-        });
+        let materials = sprite_assets.hole.clone();
+        let rect_mesh = sprite_mesh(&mut world, size);
         world.commands().entity(ctx.entity).insert((
             Mesh3d(rect_mesh),
-            MeshMaterial3d(material),
+            Billboard,
+            MeshMaterial3d(materials.normal.clone()),
             RigidBody::Static,
             Sensor,
-            Collider::from(Cuboid::default()),
+            sprite_collision_layers(),
+            sprite_collider(size),
             FocusDetails {
-                name: plant_name,
-                selectable: false,
-                text: None,
+                entity: ctx.entity,
+                interact_range,
+                name: "door".to_string(),
+                selectable: true,
+                text: Some(target),
+                // picked in handle_focus_click depending on if it's locked
                 sound_on_action: None,
-                focus_type: FocusType::Hole,
+                focus_type: FocusType::Door,
+                cursed: false,
+                action_prompt: None,
             },
+            HighlightMaterials(materials.clone()),
             LevelStuff,
         ));
     }
 }
 
+// Flips every Toggleable brush named `target` when clicked
 #[point_class(
-    model({ path: "sprites/face.png", scale: 1. }),
+    model({ path: "sprites/start.png", scale: 0.5 }),
 )]
 #[component(on_add = Self::on_add)]
-struct FaceSprite {
-    pub name: String,
+struct LeverSprite {
+    // name of the Toggleable brush(es) it flips
+    pub target: String,
+    pub sound: String,
+    pub size: f32,
+    // how close the player has to be to interact
+    pub interact_range: f32,
 }
-impl Default for FaceSprite {
+impl Default for LeverSprite {
     fn default() -> Self {
-        FaceSprite {
-            name: String::new(),
+        LeverSprite {
+            target: String::new(),
+            sound: "boing".to_string(),
+            size: DEFAULT_SMALL_SPRITE_SIZE,
+            interact_range: MAX_DIST_FOR_FOCUS,
         }
     }
 }
 
-impl FaceSprite {
+/// Whether a lever has been flipped an odd number of times
+#[derive(Component, Default)]
+struct LeverOn(bool);
+
+impl LeverSprite {
     pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
-        let Some(asset_server) = world.get_resource::<AssetServer>() else {
+        let Some(sprite_assets) = world.get_resource::<SpriteAssets>() else {
             return;
         };
 
-        let face_sprite = world.get::<FaceSprite>(ctx.entity).unwrap();
-        let face_name = face_sprite.name.clone();
+        let lever_sprite = world.get::<LeverSprite>(ctx.entity).unwrap();
+        let target = lever_sprite.target.clone();
+        let sound = lever_sprite.sound.clone();
+        let size = lever_sprite.size;
+        let interact_range = lever_sprite.interact_range;
+        if target.is_empty() {
+            warn!("LeverSprite with no target, it won't do anything");
+        }
 
-        let rect_mesh = asset_server.add(Mesh::from(Rectangle::new(1.414, 1.414)));
-        let material = asset_server.add(StandardMaterial {
-            base_color_texture: Some(asset_server.load("sprites/face.png")),
-            emissive_texture: Some(asset_server.load("sprites/face.png")),
-            emissive: Color::WHITE.into(),
-            perceptual_roughness: 1.0,
-            alpha_mode: AlphaMode::Mask(1.0),
-            cull_mode: None,
-            ..default()
-        });
+        let materials = sprite_assets.lever.clone();
+        let rect_mesh = sprite_mesh(&mut world, size);
         world.commands().entity(ctx.entity).insert((
             Mesh3d(rect_mesh),
-            MeshMaterial3d(material),
+            Billboard,
+            MeshMaterial3d(materials.normal.clone()),
             RigidBody::Static,
             Sensor,
-            Collider::from(Cuboid::default()),
+            sprite_collision_layers(),
+            sprite_collider(size),
             FocusDetails {
-                name: face_name,
-                selectable: false,
-                text: None,
-                sound_on_action: None,
-                focus_type: FocusType::Hole,
+                entity: ctx.entity,
+                interact_range,
+                name: "lever".to_string(),
+                selectable: true,
+                text: Some(target),
+                sound_on_action: Some(sound),
+                focus_type: FocusType::Lever,
+                cursed: false,
+                action_prompt: None,
             },
+            LeverOn::default(),
+            HighlightMaterials(materials.clone()),
             LevelStuff,
         ));
     }
 }
 
+/// Purely visual sprite: no collider, no FocusDetails, nothing to interact with
 #[derive(Component)]
-struct SpeedCoin {
-    respawn_timer: Option<Timer>,
-    respawn_duration: f32,
-}
-
-const DEFAULT_COIN_RESPAWN_S: f32 = 1.0;
+pub struct Decoration;
 
-impl Default for SpeedCoin {
+#[point_class(
+    model({ path: "sprites/plant.png", scale: 2. }),
+)]
+#[component(on_add = Self::on_add)]
+struct PlantSprite {
+    pub name: String,
+    pub size: f32,
+}
+impl Default for PlantSprite {
     fn default() -> Self {
-        SpeedCoin {
-            respawn_timer: None,
-            respawn_duration: DEFAULT_COIN_RESPAWN_S,
+        PlantSprite {
+            name: String::new(),
+            size: DEFAULT_LARGE_SPRITE_SIZE,
         }
     }
 }
 
-#[point_class(
-    model({ path: "sprites/coin.png", scale: .2 }),
-)]
-#[component(on_add = Self::on_add)]
-struct CoinSprite;
-
-impl CoinSprite {
+impl PlantSprite {
     pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
-        let Some(asset_server) = world.get_resource::<AssetServer>() else {
+        let Some(sprite_assets) = world.get_resource::<SpriteAssets>() else {
             return;
         };
 
-        let rect_mesh = asset_server.add(Mesh::from(Rectangle::new(0.1, 0.1)));
-        let material = asset_server.add(StandardMaterial {
-            base_color_texture: Some(asset_server.load("sprites/coin.png")),
-            emissive_texture: Some(asset_server.load("sprites/coin.png")),
-            emissive: Color::WHITE.into(),
-            perceptual_roughness: 1.0,
-            alpha_mode: AlphaMode::Mask(1.0),
-            cull_mode: None,
-            ..default()
-        });
+        let size = world.get::<PlantSprite>(ctx.entity).unwrap().size;
+
+        let material = sprite_assets.plant.clone();
+        let rect_mesh = sprite_mesh(&mut world, size);
         world.commands().entity(ctx.entity).insert((
             Mesh3d(rect_mesh),
+            Billboard,
             MeshMaterial3d(material),
-            RigidBody::Static,
-            Sensor,
-            Collider::from(Cuboid::default()),
-            FocusDetails {
-                name: "coin".to_string(),
-                selectable: false,
-                text: None,
-                sound_on_action: None,
-                focus_type: FocusType::Hole,
-            },
-            // Enable collision events for this entity.
-            CollisionEventsEnabled,
-            // Read entities colliding with this entity.
-            CollidingEntities::default(),
+            Decoration,
             LevelStuff,
-            SpeedCoin::default(),
         ));
     }
 }
 
-#[solid_class]
-pub struct CoolSolid;
-
-#[derive(Resource)]
-pub struct PlayerFocus(pub Option<FocusDetails>);
-
-const MAX_DIST_FOR_FOCUS: f32 = 2.0;
+#[point_class(
+    model({ path: "sprites/face.png", scale: 1. }),
+)]
+#[component(on_add = Self::on_add)]
+struct FaceSprite {
+    pub name: String,
+    // when set, the face works like a hole into `target`
+    pub selectable: bool,
+    pub target: String,
+    pub warp_sound: String,
+    pub size: f32,
+    // how close the player has to be to interact
+    pub interact_range: f32,
+}
+impl Default for FaceSprite {
+    fn default() -> Self {
+        FaceSprite {
+            name: String::new(),
+            selectable: false,
+            target: String::new(),
+            warp_sound: "badwarp".to_string(),
+            size: DEFAULT_LARGE_SPRITE_SIZE,
+            interact_range: MAX_DIST_FOR_FOCUS,
+        }
+    }
+}
 
-fn update_material_on<E: EntityEvent>(
-    new_material: Handle<StandardMaterial>,
-    selection_mode: Selection,
-) -> impl Fn(
-    On<E>,
-    Query<(
+impl FaceSprite {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        let Some(sprite_assets) = world.get_resource::<SpriteAssets>() else {
+            return;
+        };
+
+        let face_sprite = world.get::<FaceSprite>(ctx.entity).unwrap();
+        let face_name = face_sprite.name.clone();
+        let selectable = face_sprite.selectable;
+        let target = face_sprite.target.clone();
+        let warp_sound = face_sprite.warp_sound.clone();
+        let size = face_sprite.size;
+        let interact_range = face_sprite.interact_range;
+
+        let materials = sprite_assets.face.clone();
+        let rect_mesh = sprite_mesh(&mut world, size);
+        let mut commands = world.commands();
+        let mut face = commands.entity(ctx.entity);
+        face.insert((
+            Mesh3d(rect_mesh),
+            Billboard,
+            MeshMaterial3d(materials.normal.clone()),
+            LevelStuff,
+        ));
+
+        // purely decorative unless marked as a portal
+        if !selectable {
+            face.insert(Decoration);
+        } else {
+            face.insert((
+                RigidBody::Static,
+                Sensor,
+                sprite_collision_layers(),
+                sprite_collider(size),
+                FocusDetails {
+                    entity: ctx.entity,
+                    interact_range,
+                    name: face_name,
+                    selectable: true,
+                    text: Some(target),
+                    sound_on_action: Some(warp_sound),
+                    focus_type: FocusType::Hole,
+                    cursed: false,
+                    action_prompt: None,
+                },
+                HighlightMaterials(materials.clone()),
+            ));
+        }
+    }
+}
+
+// decorative sprite that flips through frames, e.g. an idle wiggling rat
+#[point_class(
+    model({ path: "sprites/rat.png", scale: 0.5 }),
+)]
+#[component(on_add = Self::on_add)]
+struct AnimatedSprite {
+    // comma separated image paths, played in order
+    pub frames: String,
+    pub fps: f32,
+    pub looping: bool,
+    pub size: f32,
+}
+impl Default for AnimatedSprite {
+    fn default() -> Self {
+        AnimatedSprite {
+            frames: "sprites/rat.png,sprites/rat2.png".to_string(),
+            fps: 4.0,
+            looping: true,
+            size: DEFAULT_SMALL_SPRITE_SIZE,
+        }
+    }
+}
+
+impl AnimatedSprite {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        let size = world.get::<AnimatedSprite>(ctx.entity).unwrap().size;
+        let rect_mesh = sprite_mesh(&mut world, size);
+        let Some(asset_server) = world.get_resource::<AssetServer>() else {
+            return;
+        };
+
+        let animated_sprite = world.get::<AnimatedSprite>(ctx.entity).unwrap();
+        let frames: Vec<Handle<Image>> = animated_sprite
+            .frames
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(|path| asset_server.load(path.to_string()))
+            .collect();
+        let Some(first_frame) = frames.first().cloned() else {
+            warn!("AnimatedSprite with no frames, skipping");
+            return;
+        };
+        let fps = animated_sprite.fps;
+        let looping = animated_sprite.looping;
+
+        // each animated sprite gets its own material since we swap textures on it
+        let material = asset_server.add(StandardMaterial {
+            base_color_texture: Some(first_frame.clone()),
+            emissive: Color::WHITE.into(),
+            emissive_texture: Some(first_frame),
+            perceptual_roughness: 1.0,
+            alpha_mode: AlphaMode::Mask(1.0),
+            cull_mode: None,
+            ..default()
+        });
+
+        let mut commands = world.commands();
+        let mut sprite = commands.entity(ctx.entity);
+        sprite.insert((
+            Mesh3d(rect_mesh),
+            Billboard,
+            MeshMaterial3d(material),
+            Decoration,
+            LevelStuff,
+        ));
+        // a single frame (or no speed) is just a static sprite
+        if frames.len() > 1 && fps > 0.0 {
+            sprite.insert(SpriteAnimation {
+                frames,
+                current: 0,
+                timer: Timer::from_seconds(1.0 / fps, TimerMode::Repeating),
+                looping,
+            });
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct SpriteAnimation {
+    frames: Vec<Handle<Image>>,
+    current: usize,
+    timer: Timer,
+    looping: bool,
+}
+
+fn animate_sprites(
+    mut sprites: Query<(&mut SpriteAnimation, &MeshMaterial3d<StandardMaterial>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    time: Res<Time>,
+) {
+    for (mut animation, material) in &mut sprites {
+        if !animation.timer.tick(time.delta()).just_finished() {
+            continue;
+        }
+        let next = animation.current + 1;
+        if next >= animation.frames.len() && !animation.looping {
+            // hold on the last frame
+            continue;
+        }
+        animation.current = next % animation.frames.len();
+
+        if let Some(material) = materials.get_mut(&material.0) {
+            let frame = animation.frames[animation.current].clone();
+            material.base_color_texture = Some(frame.clone());
+            material.emissive_texture = Some(frame);
+        }
+    }
+}
+
+#[derive(Component)]
+struct SpeedCoin {
+    respawn_timer: Option<Timer>,
+    respawn_duration: f32,
+    boost: f32,
+    // grabbed at least once this visit
+    collected: bool,
+}
+
+const DEFAULT_COIN_RESPAWN_S: f32 = 1.0;
+
+impl Default for SpeedCoin {
+    fn default() -> Self {
+        SpeedCoin {
+            respawn_timer: None,
+            respawn_duration: DEFAULT_COIN_RESPAWN_S,
+            boost: COIN_BOOST,
+            collected: false,
+        }
+    }
+}
+
+#[point_class(
+    model({ path: "sprites/coin.png", scale: .2 }),
+)]
+#[component(on_add = Self::on_add)]
+struct CoinSprite {
+    pub size: f32,
+    // how hard grabbing this coin launches the player
+    pub boost: f32,
+    // seconds before the coin comes back after being grabbed
+    pub respawn_duration: f32,
+}
+impl Default for CoinSprite {
+    fn default() -> Self {
+        CoinSprite {
+            size: DEFAULT_COIN_SPRITE_SIZE,
+            boost: COIN_BOOST,
+            respawn_duration: DEFAULT_COIN_RESPAWN_S,
+        }
+    }
+}
+
+impl CoinSprite {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        let Some(sprite_assets) = world.get_resource::<SpriteAssets>() else {
+            return;
+        };
+
+        let material = sprite_assets.coin.clone();
+        let coin_sprite = world.get::<CoinSprite>(ctx.entity).unwrap();
+        let size = coin_sprite.size;
+        let speed_coin = SpeedCoin {
+            boost: coin_sprite.boost,
+            respawn_duration: coin_sprite.respawn_duration,
+            ..default()
+        };
+        let rect_mesh = sprite_mesh(&mut world, size);
+        world.commands().entity(ctx.entity).insert((
+            Mesh3d(rect_mesh),
+            Billboard,
+            MeshMaterial3d(material),
+            RigidBody::Static,
+            Sensor,
+            sprite_collision_layers(),
+            sprite_collider(size),
+            FocusDetails {
+                entity: ctx.entity,
+                interact_range: MAX_DIST_FOR_FOCUS,
+                name: "coin".to_string(),
+                selectable: false,
+                text: None,
+                sound_on_action: None,
+                focus_type: FocusType::Coin,
+                cursed: false,
+                action_prompt: None,
+            },
+            // Enable collision events for this entity.
+            CollisionEventsEnabled,
+            // Read entities colliding with this entity.
+            CollidingEntities::default(),
+            LevelStuff,
+            speed_coin,
+        ));
+    }
+}
+
+// Walk into it to pick it up, opens any DoorSprite with a matching key
+// (no key art yet so it borrows the coin sprite)
+#[point_class(
+    model({ path: "sprites/coin.png", scale: .2 }),
+)]
+#[component(on_add = Self::on_add)]
+struct KeyItemSprite {
+    pub name: String,
+    pub size: f32,
+}
+impl Default for KeyItemSprite {
+    fn default() -> Self {
+        KeyItemSprite {
+            name: String::new(),
+            size: DEFAULT_COIN_SPRITE_SIZE,
+        }
+    }
+}
+
+impl KeyItemSprite {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        let Some(sprite_assets) = world.get_resource::<SpriteAssets>() else {
+            return;
+        };
+
+        let material = sprite_assets.coin.clone();
+        let size = world.get::<KeyItemSprite>(ctx.entity).unwrap().size;
+        let rect_mesh = sprite_mesh(&mut world, size);
+        world.commands().entity(ctx.entity).insert((
+            Mesh3d(rect_mesh),
+            Billboard,
+            MeshMaterial3d(material),
+            RigidBody::Static,
+            Sensor,
+            sprite_collision_layers(),
+            sprite_collider(size),
+            CollisionEventsEnabled,
+            CollidingEntities::default(),
+            LevelStuff,
+        ));
+    }
+}
+
+/// Keys the player has picked up, kept across levels
+#[derive(Resource, Default)]
+pub struct Inventory(pub HashSet<String>);
+
+fn check_for_key_pickups(
+    keys: Query<(Entity, &KeyItemSprite, &CollidingEntities)>,
+    mut inventory: ResMut<Inventory>,
+    mut commands: Commands,
+    server: Res<AssetServer>,
+) {
+    for (entity, key_item, colliding) in &keys {
+        if colliding.0.is_empty() {
+            continue;
+        }
+        inventory.0.insert(key_item.name.clone());
+        commands.spawn(SamplePlayer::new(server.load("sounds/laugh.wav")));
+        commands.entity(entity).despawn();
+    }
+}
+
+// not visible to player, touching it moves where they respawn
+#[point_class(
+    model({ path: "sprites/start.png", scale: 0.5 }),
+)]
+#[component(on_add = Self::on_add)]
+pub struct Checkpoint {
+    // edge length of the trigger cube
+    pub size: f32,
+}
+impl Default for Checkpoint {
+    fn default() -> Self {
+        Checkpoint { size: 1.0 }
+    }
+}
+
+impl Checkpoint {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        let size = world.get::<Checkpoint>(ctx.entity).unwrap().size;
+        world.commands().entity(ctx.entity).insert((
+            RigidBody::Static,
+            Sensor,
+            sprite_collision_layers(),
+            Collider::from(Cuboid::from_length(size)),
+            CollisionEventsEnabled,
+            CollidingEntities::default(),
+            LevelStuff,
+        ));
+    }
+}
+
+/// Where the player last touched a checkpoint in this level, if anywhere
+#[derive(Resource, Default)]
+pub struct LastCheckpoint(pub Option<Vec3>);
+
+fn check_for_checkpoints(
+    checkpoints: Query<(&Transform, &CollidingEntities), With<Checkpoint>>,
+    player: Single<Entity, With<PlayerCamera>>,
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+) {
+    for (checkpoint_tf, colliding) in &checkpoints {
+        if colliding.0.contains(&*player) && last_checkpoint.0 != Some(checkpoint_tf.translation) {
+            last_checkpoint.0 = Some(checkpoint_tf.translation);
+        }
+    }
+}
+
+fn reset_last_checkpoint(mut last_checkpoint: ResMut<LastCheckpoint>) {
+    last_checkpoint.0 = None;
+}
+
+// not visible, registers a named line other dialogue can reference with `@name`
+#[point_class]
+#[component(on_add = Self::on_add)]
+struct DialogueSnippet {
+    pub name: String,
+    pub text: String,
+}
+impl Default for DialogueSnippet {
+    fn default() -> Self {
+        DialogueSnippet {
+            name: String::new(),
+            text: String::new(),
+        }
+    }
+}
+
+impl DialogueSnippet {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        let snippet = world.get::<DialogueSnippet>(ctx.entity).unwrap();
+        let name = snippet.name.clone();
+        let text = snippet.text.clone();
+        if name.is_empty() {
+            warn!("DialogueSnippet with no name, ignoring");
+            return;
+        }
+        if let Some(mut library) = world.get_resource_mut::<DialogueLibrary>() {
+            library.0.insert(name, text);
+        }
+    }
+}
+
+// snippets belong to the map that defined them, so `@name` can't reach back into the last level
+fn reset_dialogue_library(mut library: ResMut<DialogueLibrary>) {
+    library.0.clear();
+}
+
+#[solid_class]
+pub struct CoolSolid;
+
+// Solid geometry that sets what the player's footsteps sound like on it, e.g. "flesh" or "tile"
+#[solid_class]
+#[derive(Default)]
+pub struct SurfaceType {
+    pub surface: String,
+}
+
+// Brush that levers can make vanish (and come back), matched up by `name`
+#[solid_class]
+#[component(on_add = Self::on_add)]
+#[derive(Default)]
+pub struct Toggleable {
+    pub name: String,
+}
+
+impl Toggleable {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        let name = world.get::<Toggleable>(ctx.entity).unwrap().name.clone();
+        if name.is_empty() {
+            warn!("Toggleable with no name, no lever can reach it");
+            return;
+        }
+        if let Some(mut registry) = world.get_resource_mut::<ToggleRegistry>() {
+            registry.0.entry(name).or_default().push(ctx.entity);
+        }
+    }
+}
+
+// Brush that slides back and forth between where it's built and `to` (an offset from there)
+#[solid_class]
+#[component(on_add = Self::on_add)]
+pub struct MovingPlatform {
+    pub to: Vec3,
+    // seconds for one trip
+    pub duration: f32,
+    // ping-pong forever, otherwise stop once it gets there
+    pub loops: bool,
+}
+impl Default for MovingPlatform {
+    fn default() -> Self {
+        MovingPlatform {
+            to: Vec3::ZERO,
+            duration: DEFAULT_PLATFORM_TRIP_S,
+            loops: true,
+        }
+    }
+}
+
+const DEFAULT_PLATFORM_TRIP_S: f32 = 4.0;
+
+impl MovingPlatform {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        world
+            .commands()
+            .entity(ctx.entity)
+            .insert((RigidBody::Kinematic, PlatformTravel::default()));
+    }
+}
+
+/// Where a platform started and how long it's been going, fresh every time the level spawns
+#[derive(Component, Default)]
+struct PlatformTravel {
+    // picked up on the first update, same as Wander
+    start: Option<Vec3>,
+    elapsed: f32,
+}
+
+fn move_platforms(
+    mut platforms: Query<(
+        &MovingPlatform,
+        &mut PlatformTravel,
+        &Position,
+        &mut LinearVelocity,
+    )>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    if dt <= 0.0 {
+        return;
+    }
+    for (platform, mut travel, position, mut lin_vel) in &mut platforms {
+        let start = *travel.start.get_or_insert(position.0);
+        travel.elapsed += dt;
+        let trips = travel.elapsed / platform.duration.max(f32::EPSILON);
+        let t = if platform.loops {
+            // there and back again
+            let phase = trips % 2.0;
+            if phase <= 1.0 { phase } else { 2.0 - phase }
+        } else {
+            trips.min(1.0)
+        };
+        // ease in and out of each end so riders don't get jerked around
+        let eased = t * t * (3.0 - 2.0 * t);
+        // velocity rather than teleporting so the physics knows it's moving
+        lin_vel.0 = (start + platform.to * eased - position.0) / dt;
+    }
+}
+
+// the dynamic player body doesn't get dragged along by friction, so carry it by hand
+fn carry_player_on_platforms(
+    platforms: Query<&LinearVelocity, (With<MovingPlatform>, Without<PlayerCamera>)>,
+    colliders: Query<&ColliderOf>,
+    player: Single<(&ShapeHits, &mut Position), (With<PlayerCamera>, With<Grounded>)>,
+    time: Res<Time>,
+) {
+    let (hits, mut position) = player.into_inner();
+    // the hit might be on a child collider, go by the body it belongs to
+    let platform_vel = hits.iter().find_map(|hit| {
+        let body = colliders.get(hit.entity).map_or(hit.entity, |of| of.body);
+        platforms.get(body).ok()
+    });
+    if let Some(platform_vel) = platform_vel {
+        position.0 += platform_vel.0 * time.delta_secs();
+    }
+}
+
+/// Toggleable brushes in the current level by name, filled in as the level spawns
+#[derive(Resource, Default)]
+pub struct ToggleRegistry(pub HashMap<String, Vec<Entity>>);
+
+fn reset_toggle_registry(mut registry: ResMut<ToggleRegistry>) {
+    registry.0.clear();
+}
+
+/// Fired when the player flips a lever
+#[derive(Event, Clone, Debug)]
+pub struct LeverPulled {
+    pub lever: Entity,
+}
+
+fn on_lever_pulled(
+    pulled: On<LeverPulled>,
+    mut levers: Query<(
+        &LeverSprite,
+        &mut LeverOn,
+        &mut HighlightMaterials,
         &mut MeshMaterial3d<StandardMaterial>,
-        &FocusDetails,
-        &Transform,
     )>,
-    ResMut<PlayerFocus>,
-    Single<&Transform, With<PlayerCamera>>,
+    mut visibilities: Query<&mut Visibility, With<Toggleable>>,
+    disabled: Query<(), With<ColliderDisabled>>,
+    registry: Res<ToggleRegistry>,
+    sprite_assets: Res<SpriteAssets>,
+    mut commands: Commands,
 ) {
-    move |trigger, mut query, mut highlighted, player_tf| {
-        if let Ok((mut material, sprite_deets, sprite_tf)) = query.get_mut(trigger.event_target()) {
-            // Only make sprite 'focused' if it's selectable and close enough
-            let dist = (sprite_tf.translation - player_tf.translation).length();
-            if sprite_deets.selectable {
-                material.0 = new_material.clone();
-                match selection_mode {
-                    Selection::Off => highlighted.0 = None,
-                    Selection::On => {
-                        // only actually select if they're close enough
-                        if dist <= MAX_DIST_FOR_FOCUS {
-                            if let Some(existing) = highlighted.0.clone() {
-                                if existing.name == sprite_deets.name {
-                                    return;
-                                }
-                            }
-                            highlighted.0 = Some(sprite_deets.clone());
-                        }
-                    }
-                }
+    let Ok((lever, mut lever_on, mut materials, mut material)) = levers.get_mut(pulled.lever)
+    else {
+        return;
+    };
+    lever_on.0 = !lever_on.0;
+    materials.0 = if lever_on.0 {
+        sprite_assets.lever_on.clone()
+    } else {
+        sprite_assets.lever.clone()
+    };
+    // still being looked at, so keep it lit
+    material.0 = materials.0.highlight.clone();
+
+    let Some(targets) = registry.0.get(&lever.target) else {
+        warn!(
+            "lever target '{}' isn't a Toggleable in this level",
+            lever.target
+        );
+        return;
+    };
+    for &target in targets {
+        let Ok(mut visibility) = visibilities.get_mut(target) else {
+            continue;
+        };
+        // flip each brush on its own, so two with the same name can swap places
+        if disabled.contains(target) {
+            *visibility = Visibility::Inherited;
+            commands.entity(target).remove::<ColliderDisabled>();
+        } else {
+            *visibility = Visibility::Hidden;
+            commands.entity(target).insert(ColliderDisabled);
+        }
+    }
+}
+
+// Invisible brush that overrides movement feel for the level it's in, put one anywhere
+#[solid_class(hooks(SceneHooks::new()))]
+#[component(on_add = Self::on_add)]
+pub struct LevelTuning {
+    pub gravity: f32,
+    pub player_speed: f32,
+    pub sprint_boost: f32,
+    // score attack, grabbing every coin clears the level
+    pub clear_on_all_coins: bool,
+    // how far up/down the player can look, in degrees from level
+    pub pitch_limit: f32,
+}
+impl Default for LevelTuning {
+    fn default() -> Self {
+        LevelTuning {
+            gravity: GRAVITY_MULT,
+            player_speed: PLAYER_SPEED,
+            sprint_boost: PLAYER_SPRINT_BOOST,
+            clear_on_all_coins: false,
+            pitch_limit: DEFAULT_PITCH_LIMIT.to_degrees(),
+        }
+    }
+}
+
+impl LevelTuning {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        world
+            .commands()
+            .entity(ctx.entity)
+            .insert(Visibility::Hidden);
+    }
+}
+
+// Invisible brush that fires a TriggerEntered with its `event` when the player walks in
+#[solid_class(hooks(SceneHooks::new().convex_collider()))]
+#[component(on_add = Self::on_add)]
+#[derive(Default)]
+pub struct TriggerVolume {
+    pub event: String,
+}
+
+impl TriggerVolume {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        world.commands().entity(ctx.entity).insert((
+            RigidBody::Static,
+            Sensor,
+            sprite_collision_layers(),
+            Visibility::Hidden,
+            CollisionEventsEnabled,
+            CollidingEntities::default(),
+            PlayerInside::default(),
+            LevelStuff,
+        ));
+    }
+}
+
+// Invisible hazard brush, walking into it counts as dying
+#[solid_class(hooks(SceneHooks::new().convex_collider()))]
+#[component(on_add = Self::on_add)]
+#[derive(Default)]
+pub struct KillVolume;
+
+impl KillVolume {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        world.commands().entity(ctx.entity).insert((
+            RigidBody::Static,
+            Sensor,
+            sprite_collision_layers(),
+            Visibility::Hidden,
+            CollisionEventsEnabled,
+            CollidingEntities::default(),
+            LevelStuff,
+        ));
+    }
+}
+
+fn check_kill_volumes(
+    volumes: Query<&CollidingEntities, With<KillVolume>>,
+    player: Single<
+        (Entity, &mut Transform, &mut LinearVelocity),
+        (With<PlayerCamera>, Without<Noclip>),
+    >,
+    level_start: Res<LevelStartLocation>,
+    last_checkpoint: Res<LastCheckpoint>,
+    mut commands: Commands,
+    server: Res<AssetServer>,
+) {
+    let (player_ent, mut player_tf, mut lin_vel) = player.into_inner();
+    if volumes
+        .iter()
+        .any(|colliding| colliding.0.contains(&player_ent))
+    {
+        reset_player_to_start(
+            &mut commands,
+            &server,
+            &mut player_tf,
+            &mut lin_vel,
+            &last_checkpoint,
+            &level_start,
+        );
+    }
+}
+
+// Brush that muffles and echoes all the audio while the player's inside, for holes and cramped rooms
+#[solid_class(hooks(SceneHooks::new().convex_collider()))]
+#[component(on_add = Self::on_add)]
+pub struct AudioZone {
+    // how much echo, 0 to 1
+    pub reverb: f32,
+    // lowpass cutoff in Hz, lower is more muffled
+    pub lowpass: f32,
+}
+impl Default for AudioZone {
+    fn default() -> Self {
+        AudioZone {
+            reverb: 0.5,
+            lowpass: 1200.0,
+        }
+    }
+}
+
+impl AudioZone {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        world.commands().entity(ctx.entity).insert((
+            RigidBody::Static,
+            Sensor,
+            sprite_collision_layers(),
+            Visibility::Hidden,
+            CollisionEventsEnabled,
+            CollidingEntities::default(),
+            LevelStuff,
+        ));
+    }
+}
+
+// Brush that pops up some narration in the text box when the player walks in
+#[solid_class(hooks(SceneHooks::new().convex_collider()))]
+#[component(on_add = Self::on_add)]
+pub struct TextTrigger {
+    pub text: String,
+    // only show it the first time
+    pub once: bool,
+    // seconds before the text box goes away on its own
+    pub duration: f32,
+}
+impl Default for TextTrigger {
+    fn default() -> Self {
+        TextTrigger {
+            text: String::new(),
+            once: true,
+            duration: DEFAULT_TEXT_TRIGGER_S,
+        }
+    }
+}
+
+const DEFAULT_TEXT_TRIGGER_S: f32 = 4.0;
+
+impl TextTrigger {
+    pub fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        world.commands().entity(ctx.entity).insert((
+            RigidBody::Static,
+            Sensor,
+            sprite_collision_layers(),
+            Visibility::Hidden,
+            CollisionEventsEnabled,
+            CollidingEntities::default(),
+            PlayerInside::default(),
+            LevelStuff,
+        ));
+    }
+}
+
+fn check_text_triggers(
+    mut triggers: Query<(Entity, &TextTrigger, &CollidingEntities, &mut PlayerInside)>,
+    player: Single<Entity, With<PlayerCamera>>,
+    text_box_query: Query<Entity, With<TextBox>>,
+    dialogue: Res<DialogueState>,
+    library: Res<DialogueLibrary>,
+    mut commands: Commands,
+    server: Res<AssetServer>,
+) {
+    for (entity, trigger, colliding, mut was_inside) in &mut triggers {
+        if !colliding.0.contains(&*player) {
+            was_inside.0 = false;
+            continue;
+        }
+        // don't talk over a conversation, it'll fire once that's done if they're still here
+        if was_inside.0 || dialogue.is_active() {
+            continue;
+        }
+        was_inside.0 = true;
+        for text_box_ent in &text_box_query {
+            commands.entity(text_box_ent).despawn();
+        }
+        let context = TextContext {
+            library: Some(&library),
+            vars: None,
+        };
+        let text_box = spawn_text_box(
+            &mut commands,
+            &server,
+            &parse_random_text_with(&trigger.text, &context),
+        );
+        commands
+            .entity(text_box)
+            .insert(DismissAfter(Timer::from_seconds(
+                trigger.duration,
+                TimerMode::Once,
+            )));
+        if trigger.once {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Fired once each time the player enters a `TriggerVolume`, observe it to script things
+#[derive(Event, Clone, Debug)]
+pub struct TriggerEntered {
+    pub event: String,
+}
+
+// whether the player was in the volume last frame, so triggers only fire on enter
+#[derive(Component, Default)]
+struct PlayerInside(bool);
+
+fn check_trigger_volumes(
+    mut triggers: Query<(&TriggerVolume, &CollidingEntities, &mut PlayerInside)>,
+    player: Single<Entity, With<PlayerCamera>>,
+    mut commands: Commands,
+) {
+    for (trigger, colliding, mut was_inside) in &mut triggers {
+        let inside = colliding.0.contains(&*player);
+        if inside && !was_inside.0 {
+            debug!("trigger entered: {}", trigger.event);
+            commands.trigger(TriggerEntered {
+                event: trigger.event.clone(),
+            });
+        }
+        was_inside.0 = inside;
+    }
+}
+
+#[derive(Resource)]
+pub struct PlayerFocus(pub Option<FocusDetails>);
+
+// default interact range, sprites can set their own
+const MAX_DIST_FOR_FOCUS: f32 = 2.0;
+// furthest the focus ray looks, nothing should have a bigger interact range than this
+const MAX_FOCUS_RAY_DIST: f32 = 20.0;
+
+/// Materials a focusable sprite swaps between as it gains and loses focus
+#[derive(Component)]
+struct HighlightMaterials(SpriteMaterials);
+
+// Light up whatever update_crosshair_focus settled on and put the last one back, so the
+// highlight always matches what a click would interact with
+fn highlight_focused_sprite(
+    focus: Res<PlayerFocus>,
+    mut last_focused: Local<Option<Entity>>,
+    mut sprites: Query<(&HighlightMaterials, &mut MeshMaterial3d<StandardMaterial>)>,
+) {
+    if !focus.is_changed() {
+        return;
+    }
+    let focused = focus.0.as_ref().map(|sprite_deets| sprite_deets.entity);
+    if focused == *last_focused {
+        return;
+    }
+    // might be gone already if the level changed
+    if let Some(previous) = *last_focused
+        && let Ok((materials, mut material)) = sprites.get_mut(previous)
+    {
+        material.0 = materials.0.normal.clone();
+    }
+    if let Some(current) = focused
+        && let Ok((materials, mut material)) = sprites.get_mut(current)
+    {
+        material.0 = materials.0.highlight.clone();
+    }
+    *last_focused = focused;
+}
+
+// Focus whatever selectable sprite is in the middle of the screen, so interacting
+// works the same whether or not the cursor is grabbed
+fn update_crosshair_focus(
+    player: Single<(Entity, &Transform), With<PlayerCamera>>,
+    spatial_query: SpatialQuery,
+    focusables: Query<&FocusDetails>,
+    sensors: Query<(), With<Sensor>>,
+    mut focus: ResMut<PlayerFocus>,
+) {
+    let (player_ent, player_tf) = *player;
+    // see through sensors (coins, checkpoints) unless they're something to interact with
+    let hit = spatial_query.cast_ray_predicate(
+        player_tf.translation,
+        player_tf.forward(),
+        MAX_FOCUS_RAY_DIST,
+        true,
+        &SpatialQueryFilter::from_mask([GameLayer::World, GameLayer::Sprite])
+            .with_excluded_entities([player_ent]),
+        &|entity| {
+            !sensors.contains(entity)
+                || focusables
+                    .get(entity)
+                    .is_ok_and(|sprite_deets| sprite_deets.selectable)
+        },
+    );
+    let new_focus = hit.and_then(|hit| {
+        focusables.get(hit.entity).ok().filter(|sprite_deets| {
+            sprite_deets.selectable && hit.distance <= sprite_deets.interact_range
+        })
+    });
+
+    let current = focus.0.as_ref().map(|sprite_deets| sprite_deets.entity);
+    if current != new_focus.map(|sprite_deets| sprite_deets.entity) {
+        focus.0 = new_focus.cloned();
+    }
+}
+
+const FOCUS_BLIP_VOLUME: f32 = 25.0;
+
+// Quiet high blip whenever something new becomes interactable
+fn play_focus_blip(
+    focus: Res<PlayerFocus>,
+    mut last_focused: Local<Option<Entity>>,
+    mut commands: Commands,
+    server: Res<AssetServer>,
+) {
+    if !focus.is_changed() {
+        return;
+    }
+    let focused = focus.0.as_ref().map(|sprite_deets| sprite_deets.entity);
+    if focused.is_some() && focused != *last_focused {
+        // default pool, so it goes through the sfx bus
+        commands.spawn((
+            SamplePlayer::new(server.load("sounds/step1.wav"))
+                .with_volume(Volume::from_percent(FOCUS_BLIP_VOLUME)),
+            bevy_seedling::sample::PlaybackSettings {
+                speed: get_scalar_boosted_rand_sfx_speed(1.0) * 2.5,
+                ..default()
+            },
+        ));
+    }
+    *last_focused = focused;
+}
+
+#[derive(Component)]
+struct HoleSFX;
+
+#[derive(Component)]
+struct RatVoice;
+
+/// Level a hole is taking us to, waiting on the screen to go black
+#[derive(Resource, Default)]
+pub struct PendingWarp(pub Option<String>);
+
+/// Named PlayerStart the next level should put the player at, if any
+#[derive(Resource, Default)]
+pub struct TargetStart(pub Option<String>);
+
+#[allow(clippy::too_many_arguments)]
+fn warp_when_faded(
+    mut pending_warp: ResMut<PendingWarp>,
+    fades: Query<&TransitionFade>,
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    scene: Single<Entity, With<SceneInstance>>,
+    level_stuff: Query<Entity, (With<LevelStuff>, Without<LevelBgm>)>,
+    level_bgm: Query<Entity, With<LevelBgm>>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut previous_level: ResMut<PreviousLevel>,
+) {
+    if pending_warp.0.is_none() || !fades.iter().any(TransitionFade::is_black) {
+        return;
+    }
+    let Some(next_level) = pending_warp.0.take() else {
+        return;
+    };
+
+    // despawn old level
+    for stuff_ent in &level_stuff {
+        commands.entity(stuff_ent).despawn();
+    }
+    // let the old bgm tail out instead of popping
+    for bgm_ent in &level_bgm {
+        commands.entity(bgm_ent).insert(FadeOut::default());
+    }
+    commands.entity(*scene).despawn();
+    // set game state to loading, spawn_warp_level picks it up from there
+    next_state.set(GameState::Loading);
+    previous_level.0 = Some(std::mem::replace(&mut current_level.0, next_level));
+}
+
+// Kicks off the load of the level warp_when_faded picked (already warm if it was preloaded).
+// Runs after the level resets, otherwise a preloaded level's snippets and toggles could
+// register before the resets wipe them
+fn spawn_warp_level(
+    current_level: Res<CurrentLevel>,
+    server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    commands.spawn(SceneRoot(server.load(level_scene_path(&current_level.0))));
+}
+
+/// Level we warped in from, somewhere safe to go back to if the new one won't load
+#[derive(Resource, Default)]
+pub struct PreviousLevel(Option<String>);
+
+const LEVEL_LOAD_FAILED_TEXT: &str =
+    "<the hole goes nowhere|nothing down there|that hole's a dead end>... (couldn't load {level})";
+const LEVEL_LOAD_FAILED_S: f32 = 4.0;
+
+// A hole pointing at a map that doesn't exist would leave us stuck loading forever,
+// so say so and head back where we came from
+#[allow(clippy::too_many_arguments)]
+fn recover_from_failed_level_load(
+    scenes: Query<(Entity, &SceneRoot)>,
+    server: Res<AssetServer>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut previous_level: ResMut<PreviousLevel>,
+    mut target_start: ResMut<TargetStart>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut templates: DialogueTemplates,
+    mut commands: Commands,
+) {
+    for (scene_ent, scene_root) in &scenes {
+        if !matches!(server.load_state(&scene_root.0), LoadState::Failed(_)) {
+            continue;
+        }
+        error!("couldn't load level '{}'", current_level.0);
+        commands.entity(scene_ent).despawn();
+
+        let vars = HashMap::from([("level".to_string(), current_level.0.clone())]);
+        let text_box = spawn_text_box(
+            &mut commands,
+            &server,
+            &templates.render(LEVEL_LOAD_FAILED_TEXT, Some(&vars)),
+        );
+        commands
+            .entity(text_box)
+            .insert(DismissAfter(Timer::from_seconds(
+                LEVEL_LOAD_FAILED_S,
+                TimerMode::Once,
+            )));
+
+        match previous_level.0.take() {
+            Some(previous) if previous != current_level.0 => {
+                target_start.0 = None;
+                commands.spawn(SceneRoot(server.load(level_scene_path(&previous))));
+                current_level.0 = previous;
             }
+            // nowhere to go back to
+            _ => next_state.set(GameState::Title),
         }
     }
 }
 
-#[derive(Component)]
-struct HoleSFX;
+fn level_scene_path(level: &str) -> String {
+    format!("maps/{level}#Scene")
+}
+
+/// Scenes for the levels reachable from the current one, held so they're loaded
+/// in the background before the player dives in
+#[derive(Resource, Default)]
+pub struct PreloadedLevels(HashMap<String, Handle<Scene>>);
+
+fn preload_reachable_levels(
+    holes: Query<&HoleSprite>,
+    doors: Query<&DoorSprite>,
+    server: Res<AssetServer>,
+    mut preloaded: ResMut<PreloadedLevels>,
+) {
+    let reachable: HashSet<&str> = holes
+        .iter()
+        .map(|hole| hole.hole_target.as_str())
+        .chain(doors.iter().map(|door| door.target.as_str()))
+        .filter(|level| !level.is_empty())
+        .collect();
+    // let go of anything we can't get to from here anymore
+    preloaded
+        .0
+        .retain(|level, _| reachable.contains(level.as_str()));
+    for level in reachable {
+        if !preloaded.0.contains_key(level) {
+            debug!("preloading level {level}");
+            preloaded
+                .0
+                .insert(level.to_string(), server.load(level_scene_path(level)));
+        }
+    }
+}
+
+// splits NPC text into lines that get shown one click at a time
+const DIALOGUE_LINE_SEPARATOR: &str = "//";
+
+/// Conversation in progress with an NPC, one line per click until it runs out
+#[derive(Resource, Default)]
+pub struct DialogueState {
+    lines: Vec<String>,
+    line: usize,
+    // who's talking, empty for nobody in particular
+    speaker: String,
+    // and the sprite doing it
+    speaker_entity: Option<Entity>,
+    cursed: bool,
+}
+
+impl DialogueState {
+    pub fn is_active(&self) -> bool {
+        self.line < self.lines.len()
+    }
+
+    fn current_line(&self) -> Option<&String> {
+        self.lines.get(self.line)
+    }
+}
+
+/// Player state that dialogue can show with `{name}`, like `{player_coins}`
+#[derive(SystemParam)]
+struct DialogueVars<'w> {
+    current_level: Res<'w, CurrentLevel>,
+    coin_count: Res<'w, CoinCount>,
+}
 
-#[derive(Component)]
-struct RatVoice;
+impl DialogueVars<'_> {
+    fn get(&self) -> HashMap<String, String> {
+        dialogue_vars(&self.current_level.0, self.coin_count.0)
+    }
+}
+
+fn dialogue_vars(level: &str, coins: u32) -> HashMap<String, String> {
+    HashMap::from([
+        ("level".to_string(), level.to_string()),
+        ("player_coins".to_string(), coins.to_string()),
+    ])
+}
 
+#[allow(clippy::too_many_arguments)]
 fn handle_focus_click(
     highlighted: Res<PlayerFocus>,
-    text_box_query: Query<Entity, With<TextBox>>,
+    mut text_box_query: Query<(Entity, Option<&mut TypewriterText>), With<TextBox>>,
     voice_query: Query<Entity, With<RatVoice>>,
+    subtitle_query: Query<Entity, With<Subtitle>>,
     mut commands: Commands,
-    mut next_state: ResMut<NextState<GameState>>,
-    scene: Single<Entity, With<SceneInstance>>,
-    level_stuff: Query<Entity, With<LevelStuff>>,
     server: Res<AssetServer>,
+    mut templates: DialogueTemplates,
+    dialogue_vars: DialogueVars,
+    mut dialogue: ResMut<DialogueState>,
+    mut pending_warp: ResMut<PendingWarp>,
+    mut target_start: ResMut<TargetStart>,
+    holes: Query<&HoleSprite>,
+    doors: Query<&DoorSprite>,
+    inventory: Res<Inventory>,
+    mut warp_cooldown: ResMut<WarpCooldown>,
+    settings: Res<Persistent<GameSettings>>,
 ) {
-    // if textbox exists, make it go away (dirty)
-    for text_box_ent in &text_box_query {
+    let vars = dialogue_vars.get();
+
+    // if textbox exists, move on to the next line or make it go away (dirty)
+    for (text_box_ent, typewriter) in &mut text_box_query {
+        // still typing, first click just shows the whole line
+        if let Some(mut typewriter) = typewriter
+            && !typewriter.is_done()
+        {
+            typewriter.finish();
+            return;
+        }
         commands.entity(text_box_ent).despawn();
+        dialogue.line += 1;
+        match dialogue.current_line() {
+            Some(line) => {
+                spawn_dialogue_box(
+                    &mut commands,
+                    &server,
+                    &dialogue.speaker,
+                    dialogue.cursed,
+                    &templates.render(line, Some(&vars)),
+                );
+            }
+            None => *dialogue = DialogueState::default(),
+        }
         // return early
         return;
     }
@@ -464,66 +1915,586 @@ fn handle_focus_click(
     }
 
     if let Some(sprite_deets) = &highlighted.0 {
+        // holes and doors don't respond for a moment after a warp starts or a level loads
+        if matches!(sprite_deets.focus_type, FocusType::Hole | FocusType::Door)
+            && warp_cooldown.0.is_some()
+        {
+            return;
+        }
         // play noise if we got one
         if let Some(sound_name) = sprite_deets.sound_on_action.clone() {
             let sound_path = format!("sounds/{sound_name}.wav");
             match sprite_deets.focus_type {
-                FocusType::Hole => {
+                FocusType::Hole | FocusType::Door => {
                     commands.spawn((SamplePlayer::new(server.load(sound_path)), HoleSFX));
                 }
                 FocusType::NPC => {
-                    commands.spawn((SamplePlayer::new(server.load(sound_path)), RatVoice));
+                    // voice comes from the rat itself
+                    commands.spawn((
+                        SamplePlayer::new(server.load(sound_path)),
+                        SpatialPool,
+                        Transform::default(),
+                        ChildOf(sprite_deets.entity),
+                        RatVoice,
+                    ));
+                }
+                FocusType::Lever => {
+                    commands.spawn((
+                        SamplePlayer::new(server.load(sound_path)),
+                        SpatialPool,
+                        Transform::default(),
+                        ChildOf(sprite_deets.entity),
+                    ));
                 }
+                FocusType::Coin | FocusType::Decoration => {}
             };
         }
 
         match sprite_deets.focus_type {
             FocusType::Hole => {
-                // Load new level
                 if let Some(next_level) = &sprite_deets.text {
-                    if next_level.len() > 0 {
-                        // despawn old level
-                        for stuff_ent in &level_stuff {
-                            commands.entity(stuff_ent).despawn();
-                        }
-                        commands.entity(*scene).despawn();
-                        // set game state to loading
-                        next_state.set(GameState::Loading);
-                        // kick off load of new level
-                        let new_level_asset = format!("maps/{next_level}#Scene");
-                        commands.spawn(SceneRoot(server.load(new_level_asset)));
-                    }
+                    let start = holes
+                        .get(sprite_deets.entity)
+                        .map(|hole| hole.target_start.as_str())
+                        .unwrap_or_default();
+                    start_warp(
+                        &mut commands,
+                        &mut pending_warp,
+                        &mut target_start,
+                        &mut warp_cooldown,
+                        next_level,
+                        start,
+                        &settings,
+                    );
+                }
+            }
+            FocusType::Door => {
+                let Ok(door) = doors.get(sprite_deets.entity) else {
+                    return;
+                };
+                if inventory.0.contains(&door.key) {
+                    commands.spawn((SamplePlayer::new(server.load("sounds/warp.wav")), HoleSFX));
+                    start_warp(
+                        &mut commands,
+                        &mut pending_warp,
+                        &mut target_start,
+                        &mut warp_cooldown,
+                        &door.target,
+                        &door.target_start,
+                        &settings,
+                    );
+                } else {
+                    commands.spawn(SamplePlayer::new(server.load("sounds/badwarp.wav")));
+                    let mut vars = vars.clone();
+                    vars.insert("key".to_string(), door.key.clone());
+                    spawn_text_box(
+                        &mut commands,
+                        &server,
+                        &templates.render(DOOR_LOCKED_TEXT, Some(&vars)),
+                    );
                 }
             }
             FocusType::NPC => {
                 if let Some(sprite_text) = &sprite_deets.text {
-                    commands.spawn((
-                        Node {
-                            position_type: PositionType::Absolute,
-                            bottom: vh(10),
-                            left: vw(15),
-                            right: vw(15),
-                            padding: UiRect::all(px(20)),
-                            ..default()
-                        },
-                        TextBox,
-                        BackgroundColor {
-                            0: Color::Oklcha(Oklcha::new(0.1788, 0.0099, 288.85, 1.0)),
-                        },
-                        children![(
-                            Text::new(parse_random_text(sprite_text)),
-                            TextColor(TEXT_COLOR),
-                            TextFont {
-                                font: server.load(SERIF_FONT_PATH),
-                                font_size: 18.0,
-                                ..default()
-                            },
-                        )],
-                    ));
+                    // start the conversation from the top
+                    *dialogue = DialogueState {
+                        lines: sprite_text
+                            .split(DIALOGUE_LINE_SEPARATOR)
+                            .map(|line| line.trim().to_string())
+                            .collect(),
+                        line: 0,
+                        speaker: sprite_deets.name.clone(),
+                        speaker_entity: Some(sprite_deets.entity),
+                        cursed: sprite_deets.cursed,
+                    };
+                    if let Some(line) = dialogue.current_line() {
+                        let line = templates.render(line, Some(&vars));
+                        spawn_dialogue_box(
+                            &mut commands,
+                            &server,
+                            &dialogue.speaker,
+                            dialogue.cursed,
+                            &line,
+                        );
+                        // caption the voice with the line it goes with
+                        if sprite_deets.sound_on_action.is_some() && settings.subtitles_enabled() {
+                            for subtitle_ent in &subtitle_query {
+                                commands.entity(subtitle_ent).despawn();
+                            }
+                            spawn_subtitle(
+                                &mut commands,
+                                &server,
+                                subtitle_text(&dialogue.speaker, &line),
+                            );
+                        }
+                    }
                 }
             }
+            FocusType::Lever => {
+                commands.trigger(LeverPulled {
+                    lever: sprite_deets.entity,
+                });
+            }
+            FocusType::Coin | FocusType::Decoration => {}
+        }
+    }
+}
+
+const NPC_GREETING_COOLDOWN_S: f32 = 20.0;
+// percent, quieter than talking to them on purpose
+const NPC_GREETING_VOLUME: f32 = 60.0;
+
+/// Time left before an NPC will greet the player again
+#[derive(Component, Default)]
+struct GreetingCooldown(Option<Timer>);
+
+// NPCs mutter their voice line when the player walks into them, so levels feel lived in
+#[allow(clippy::too_many_arguments)]
+fn greet_nearby_npcs(
+    mut npcs: Query<(
+        &FocusDetails,
+        &CollidingEntities,
+        &mut PlayerInside,
+        &mut GreetingCooldown,
+    )>,
+    player: Single<Entity, With<PlayerCamera>>,
+    subtitle_query: Query<Entity, With<Subtitle>>,
+    dialogue: Res<DialogueState>,
+    mut templates: DialogueTemplates,
+    dialogue_vars: DialogueVars,
+    time: Res<Time>,
+    server: Res<AssetServer>,
+    settings: Res<Persistent<GameSettings>>,
+    mut commands: Commands,
+) {
+    let vars = dialogue_vars.get();
+    for (sprite_deets, colliding, mut was_inside, mut cooldown) in &mut npcs {
+        if let Some(timer) = &mut cooldown.0
+            && timer.tick(time.delta()).is_finished()
+        {
+            cooldown.0 = None;
+        }
+        let inside = colliding.0.contains(&*player);
+        let entered = inside && !was_inside.0;
+        was_inside.0 = inside;
+        if !entered || cooldown.0.is_some() || dialogue.is_active() {
+            continue;
+        }
+        let Some(sound_name) = &sprite_deets.sound_on_action else {
+            continue;
+        };
+        cooldown.0 = Some(Timer::from_seconds(
+            NPC_GREETING_COOLDOWN_S,
+            TimerMode::Once,
+        ));
+        commands.spawn((
+            SamplePlayer::new(server.load(format!("sounds/{sound_name}.wav")))
+                .with_volume(Volume::from_percent(NPC_GREETING_VOLUME)),
+            SpatialPool,
+            Transform::default(),
+            ChildOf(sprite_deets.entity),
+        ));
+        // nothing is on screen for a greeting, so caption it with the rat's opening line
+        if settings.subtitles_enabled()
+            && let Some(line) = sprite_deets
+                .text
+                .as_deref()
+                .and_then(|text| text.split(DIALOGUE_LINE_SEPARATOR).next())
+        {
+            for subtitle_ent in &subtitle_query {
+                commands.entity(subtitle_ent).despawn();
+            }
+            let line = templates.render(line.trim(), Some(&vars));
+            spawn_subtitle(
+                &mut commands,
+                &server,
+                subtitle_text(&sprite_deets.name, &line),
+            );
+        }
+    }
+}
+
+/// How often NPCs near the player chatter to themselves, and how near counts
+#[derive(Resource)]
+pub struct ChatterSettings {
+    // average seconds between lines
+    pub interval: f32,
+    pub radius: f32,
+}
+
+impl Default for ChatterSettings {
+    fn default() -> Self {
+        ChatterSettings {
+            interval: 12.0,
+            radius: 10.0,
+        }
+    }
+}
+
+// percent, background mumbling
+const CHATTER_VOLUME: f32 = 35.0;
+
+fn next_chatter_delay(chatter: &ChatterSettings) -> Timer {
+    let delay = chatter.interval * rand::rng().random_range(0.5..1.5);
+    Timer::from_seconds(delay, TimerMode::Once)
+}
+
+// Every so often a random rat within earshot says its line to nobody
+#[allow(clippy::too_many_arguments)]
+fn npc_idle_chatter(
+    npcs: Query<(&FocusDetails, &GlobalTransform)>,
+    player: Single<&Transform, With<PlayerCamera>>,
+    chatter: Res<ChatterSettings>,
+    dialogue: Res<DialogueState>,
+    time: Res<Time>,
+    server: Res<AssetServer>,
+    mut commands: Commands,
+    mut next_line: Local<Option<Timer>>,
+) {
+    let timer = next_line.get_or_insert_with(|| next_chatter_delay(&chatter));
+    if !timer.tick(time.delta()).is_finished() {
+        return;
+    }
+    *timer = next_chatter_delay(&chatter);
+
+    let earshot: Vec<_> = npcs
+        .iter()
+        .filter(|(sprite_deets, tf)| {
+            sprite_deets.focus_type == FocusType::NPC
+                && Some(sprite_deets.entity) != dialogue.speaker_entity
+                && tf.translation().distance(player.translation) <= chatter.radius
+        })
+        .filter_map(|(sprite_deets, _)| {
+            Some((sprite_deets.entity, sprite_deets.sound_on_action.as_ref()?))
+        })
+        .collect();
+    let Some((entity, sound_name)) = earshot.choose(&mut rand::rng()) else {
+        return;
+    };
+    commands.spawn((
+        SamplePlayer::new(server.load(format!("sounds/{sound_name}.wav")))
+            .with_volume(Volume::from_percent(CHATTER_VOLUME)),
+        SpatialPool,
+        Transform::default(),
+        ChildOf(*entity),
+    ));
+}
+
+const SUBTITLE_S: f32 = 3.0;
+
+/// Caption for a voice line, separate from the dialogue box and gone on its own after a bit
+#[derive(Component)]
+struct Subtitle;
+
+/// Despawns the popup it's on once the timer runs out
+#[derive(Component)]
+struct DismissAfter(Timer);
+
+const TYPEWRITER_CPS: f32 = 40.0;
+
+/// Reveals a text box's body a few characters at a time
+#[derive(Component)]
+struct TypewriterText {
+    full: String,
+    revealed: usize,
+    // characters per second
+    cps: f32,
+    elapsed: f32,
+}
+
+impl TypewriterText {
+    fn new(full: &str) -> Self {
+        TypewriterText {
+            full: full.to_string(),
+            revealed: 0,
+            cps: TYPEWRITER_CPS,
+            elapsed: 0.0,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.revealed >= self.full.chars().count()
+    }
+
+    fn finish(&mut self) {
+        self.revealed = self.full.chars().count();
+    }
+
+    fn visible(&self) -> &str {
+        match self.full.char_indices().nth(self.revealed) {
+            Some((end, _)) => &self.full[..end],
+            None => &self.full,
+        }
+    }
+}
+
+fn reveal_typewriter_text(
+    mut typewriters: Query<(&mut TypewriterText, &Children)>,
+    mut bodies: Query<&mut Text, With<TextBoxBody>>,
+    time: Res<Time>,
+) {
+    for (mut typewriter, children) in &mut typewriters {
+        if !typewriter.is_done() {
+            typewriter.elapsed += time.delta_secs();
+            typewriter.revealed = typewriter
+                .revealed
+                .max((typewriter.elapsed * typewriter.cps) as usize);
+        }
+        for child in children {
+            if let Ok(mut text) = bodies.get_mut(*child)
+                && text.0 != typewriter.visible()
+            {
+                text.0 = typewriter.visible().to_string();
+            }
+        }
+    }
+}
+
+// the line being said, with who's saying it
+fn subtitle_text(speaker: &str, line: &str) -> String {
+    format!("{speaker}: {line}")
+}
+
+fn spawn_subtitle(commands: &mut Commands, server: &AssetServer, text: String) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: vh(8),
+            left: vw(30),
+            right: vw(30),
+            justify_content: JustifyContent::Center,
+            padding: UiRect::axes(px(12), px(6)),
+            ..default()
+        },
+        Subtitle,
+        DismissAfter(Timer::from_seconds(SUBTITLE_S, TimerMode::Once)),
+        BackgroundColor {
+            0: Color::Oklcha(Oklcha::new(0.1788, 0.0099, 288.85, 0.8)),
+        },
+        children![(
+            Text::new(text),
+            TextColor(TEXT_COLOR),
+            TextFont {
+                font: server.load(SERIF_FONT_PATH),
+                font_size: 18.0,
+                ..default()
+            },
+        )],
+    ));
+}
+
+fn dismiss_timed_popups(
+    mut popups: Query<(Entity, &mut DismissAfter)>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (ent, mut dismiss_after) in &mut popups {
+        if dismiss_after.0.tick(time.delta()).is_finished() {
+            commands.entity(ent).despawn();
+        }
+    }
+}
+
+const DOOR_LOCKED_TEXT: &str = "<it's locked|locked tight|won't budge>... <need the {key}|where's the {key}?|the {key} would open it>";
+
+const WARP_COOLDOWN_S: f32 = 0.5;
+
+/// Running while holes and doors should ignore clicks
+#[derive(Resource, Default)]
+pub struct WarpCooldown(Option<Timer>);
+
+impl WarpCooldown {
+    fn start(&mut self) {
+        self.0 = Some(Timer::from_seconds(WARP_COOLDOWN_S, TimerMode::Once));
+    }
+}
+
+fn no_warp_pending(pending_warp: Res<PendingWarp>) -> bool {
+    pending_warp.0.is_none()
+}
+
+fn start_warp_cooldown(mut warp_cooldown: ResMut<WarpCooldown>) {
+    warp_cooldown.start();
+}
+
+fn tick_warp_cooldown(mut warp_cooldown: ResMut<WarpCooldown>, time: Res<Time>) {
+    if let Some(timer) = &mut warp_cooldown.0
+        && timer.tick(time.delta()).is_finished()
+    {
+        warp_cooldown.0 = None;
+    }
+}
+
+// Fade to black, the level swap happens in warp_when_faded once it's dark
+fn start_warp(
+    commands: &mut Commands,
+    pending_warp: &mut PendingWarp,
+    target_start: &mut TargetStart,
+    warp_cooldown: &mut WarpCooldown,
+    level: &str,
+    start: &str,
+    settings: &GameSettings,
+) {
+    if level.is_empty() || pending_warp.0.is_some() {
+        return;
+    }
+    warp_cooldown.start();
+    pending_warp.0 = Some(level.to_string());
+    target_start.0 = Some(start.to_string()).filter(|name| !name.is_empty());
+    commands.spawn(transition_fade(settings));
+}
+
+/// The styled box along the bottom of the screen used for dialogue and narration
+pub fn spawn_text_box(commands: &mut Commands, server: &AssetServer, text: &str) -> Entity {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: vh(10),
+                left: vw(15),
+                right: vw(15),
+                padding: UiRect::all(px(20)),
+                flex_direction: FlexDirection::Column,
+                row_gap: px(8),
+                ..default()
+            },
+            TextBox,
+            BackgroundColor {
+                0: Color::Oklcha(Oklcha::new(0.1788, 0.0099, 288.85, 1.0)),
+            },
+            children![(
+                Text::new(text),
+                TextBoxBody,
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: server.load(SERIF_FONT_PATH),
+                    font_size: 18.0,
+                    ..default()
+                },
+            )],
+        ))
+        .id()
+}
+
+// how far off each glyph's size can land, as a fraction of the body's font size
+const JITTER_SIZE_RANGE: std::ops::RangeInclusive<f32> = 0.75..=1.4;
+
+/// Fills a text box's body one glyph per span, each a slightly wrong size.
+/// Sizes are rolled once when it's added, so the text doesn't swim around
+#[derive(Component)]
+#[component(on_add = Self::on_add)]
+struct JitterText(String);
+
+impl JitterText {
+    fn on_add(mut world: DeferredWorld, ctx: HookContext) {
+        let text = world.get::<JitterText>(ctx.entity).unwrap().0.clone();
+        let Some(body) = world.get::<Children>(ctx.entity).and_then(|children| {
+            children
+                .iter()
+                .find(|child| world.entity(*child).contains::<TextBoxBody>())
+        }) else {
+            return;
+        };
+        let Some(base_font) = world.get::<TextFont>(body).cloned() else {
+            return;
+        };
+        let mut rng = rand::rng();
+        let mut commands = world.commands();
+        for glyph in text.chars() {
+            let span = commands
+                .spawn((
+                    TextSpan::new(glyph.to_string()),
+                    TextColor(TEXT_COLOR),
+                    TextFont {
+                        font_size: base_font.font_size * rng.random_range(JITTER_SIZE_RANGE),
+                        ..base_font.clone()
+                    },
+                ))
+                .id();
+            commands.entity(body).add_child(span);
+        }
+    }
+}
+
+// how dark the screen gets behind an NPC conversation
+const DIALOGUE_DIM_ALPHA: f32 = 0.35;
+
+/// Fullscreen shade behind the dialogue box while a conversation is going
+#[derive(Component)]
+struct DialogueDim;
+
+fn update_dialogue_dim(
+    dialogue: Res<DialogueState>,
+    dims: Query<Entity, With<DialogueDim>>,
+    mut commands: Commands,
+) {
+    match (dialogue.is_active(), dims.is_empty()) {
+        (true, true) => {
+            commands.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    top: px(0),
+                    left: px(0),
+                    right: px(0),
+                    bottom: px(0),
+                    ..default()
+                },
+                // under the text box and the rest of the UI
+                GlobalZIndex(-1),
+                BackgroundColor(Color::Oklcha(Oklcha::new(
+                    0.1788,
+                    0.0099,
+                    288.85,
+                    DIALOGUE_DIM_ALPHA,
+                ))),
+                DialogueDim,
+            ));
+        }
+        (false, false) => {
+            for dim in &dims {
+                commands.entity(dim).despawn();
+            }
         }
+        _ => {}
+    }
+}
+
+/// The main text inside a text box, as opposed to the speaker's name
+#[derive(Component)]
+struct TextBoxBody;
+
+// Text box with the speaker's name on top, plain text box if they don't have one
+fn spawn_dialogue_box(
+    commands: &mut Commands,
+    server: &AssetServer,
+    speaker: &str,
+    cursed: bool,
+    text: &str,
+) -> Entity {
+    // body starts empty and gets typed out (or spread over jittery spans)
+    let text_box = spawn_text_box(commands, server, "");
+    if cursed {
+        commands
+            .entity(text_box)
+            .insert(JitterText(text.to_string()));
+    } else {
+        commands.entity(text_box).insert(TypewriterText::new(text));
+    }
+    if !speaker.is_empty() {
+        let header = commands
+            .spawn((
+                Text::new(speaker),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: server.load(SANS_FONT_PATH),
+                    font_size: 20.0,
+                    ..default()
+                },
+            ))
+            .id();
+        commands.entity(text_box).insert_children(0, &[header]);
     }
+    text_box
 }
 
 // Plugin for keeping billboard sprites facing the camera
@@ -531,30 +2502,129 @@ pub struct BillboardSpritePlugin;
 impl Plugin for BillboardSpritePlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource::<PlayerFocus>(PlayerFocus(None))
+            .init_resource::<DialogueLibrary>()
+            .init_resource::<TemplateCache>()
+            .init_resource::<SpriteAssets>()
+            .init_resource::<DialogueState>()
+            .init_resource::<CoinCount>()
+            .init_resource::<PendingWarp>()
+            .init_resource::<TargetStart>()
+            .init_resource::<LastCheckpoint>()
+            .init_resource::<Inventory>()
+            .init_resource::<WarpCooldown>()
+            .init_resource::<LevelClearFired>()
+            .init_resource::<PreloadedLevels>()
+            .init_resource::<PreviousLevel>()
+            .init_resource::<ChatterSettings>()
+            .init_resource::<ToggleRegistry>()
+            .add_observer(on_level_cleared)
+            .add_observer(on_lever_pulled)
+            // so a click can't land on a hole in the new level the moment it shows up
+            .add_systems(
+                OnTransition {
+                    exited: GameState::Loading,
+                    entered: GameState::InGame,
+                },
+                (start_warp_cooldown, preload_reachable_levels),
+            )
+            .add_systems(
+                OnTransition {
+                    exited: GameState::InGame,
+                    entered: GameState::Loading,
+                },
+                (
+                    (
+                        reset_coin_count,
+                        reset_last_checkpoint,
+                        reset_level_clear,
+                        reset_toggle_registry,
+                        reset_dialogue_library,
+                    ),
+                    spawn_warp_level,
+                )
+                    .chain(),
+            )
+            .add_systems(Update, npc_idle_chatter.run_if(in_state(GameState::InGame)))
+            .add_systems(
+                FixedUpdate,
+                (
+                    wander_npcs,
+                    follow_player,
+                    move_platforms,
+                    carry_player_on_platforms.after(move_platforms),
+                    // fixed step so the overlap has caught up with the teleport before it's checked again
+                    check_kill_volumes,
+                )
+                    .run_if(in_state(GameState::InGame)),
+            )
             .add_systems(
                 Update,
                 (
-                    update_billboards::<NPCSprite>.run_if(in_state(GameState::InGame)),
-                    update_billboards::<HoleSprite>.run_if(in_state(GameState::InGame)),
-                    update_billboards::<PlantSprite>.run_if(in_state(GameState::InGame)),
-                    update_billboards::<CoinSprite>.run_if(in_state(GameState::InGame)),
-                    update_billboards::<FaceSprite>.run_if(in_state(GameState::InGame)),
+                    update_billboards.run_if(in_state(GameState::InGame)),
+                    animate_sprites.run_if(in_state(GameState::InGame)),
                     check_for_coin_intersections.run_if(in_state(GameState::InGame)),
                     update_coin_respawn.run_if(in_state(GameState::InGame)),
-                    // This last one should be last in the chain because it can despawn levels
+                    check_level_cleared.run_if(in_state(GameState::InGame)),
+                    check_for_checkpoints.run_if(in_state(GameState::InGame)),
+                    check_for_key_pickups.run_if(in_state(GameState::InGame)),
+                    check_trigger_volumes.run_if(in_state(GameState::InGame)),
+                    greet_nearby_npcs.run_if(in_state(GameState::InGame)),
+                    dismiss_timed_popups,
+                    recover_from_failed_level_load.run_if(in_state(GameState::Loading)),
+                    reveal_typewriter_text,
+                    update_dialogue_dim,
+                    tick_warp_cooldown,
+                    check_text_triggers.run_if(in_state(GameState::InGame)),
+                    // These last ones should be last in the chain because they can despawn levels
+                    warp_when_faded.run_if(in_state(GameState::InGame)),
+                    // no poking at things mid-fade either
+                    update_crosshair_focus
+                        .run_if(in_state(GameState::InGame))
+                        .run_if(no_warp_pending),
+                    (play_focus_blip, highlight_focused_sprite).after(update_crosshair_focus),
                     handle_focus_click
+                        .after(update_crosshair_focus)
                         .run_if(in_state(GameState::InGame))
-                        .run_if(input_just_pressed(MouseButton::Left)),
+                        .run_if(no_warp_pending)
+                        .run_if(
+                            input_just_pressed(MouseButton::Left).or(interact_key_just_pressed),
+                        ),
                 ),
             );
     }
 }
 
 const COIN_BOOST: f32 = 100.0;
+const COIN_SHAKE_TRAUMA: f32 = 0.6;
+// fraction of the boost that always goes straight up
+const COIN_BOOST_LIFT: f32 = 0.1;
+
+/// Direction a coin launches the player. Only the yaw of `facing` counts, so looking
+/// straight ahead and looking at your feet give the same launch: forward along the
+/// ground, tipped up by `COIN_BOOST_LIFT` (e.g. facing -Z gives roughly (0, 0.1, -1)
+/// before scaling). Looking straight up or down still gives a flat forward launch.
+fn coin_boost_dir(facing: &Transform) -> Vec3 {
+    let (yaw, _, _) = facing.rotation.to_euler(EulerRot::YXZ);
+    let flat_forward = Quat::from_rotation_y(yaw) * Vec3::NEG_Z;
+    (flat_forward + Vec3::Y * COIN_BOOST_LIFT).normalize_or_zero()
+}
+
+/// Coins grabbed in the current level
+#[derive(Resource, Default)]
+pub struct CoinCount(pub u32);
+
+fn reset_coin_count(mut coin_count: ResMut<CoinCount>) {
+    coin_count.0 = 0;
+}
 
+#[allow(clippy::too_many_arguments)]
 fn check_for_coin_intersections(
     mut coin_query: Query<(&mut SpeedCoin, &CollidingEntities, &mut Visibility)>,
     mut player: Single<(&Transform, &mut LinearVelocity), With<PlayerCamera>>,
+    mut coin_count: ResMut<CoinCount>,
+    mut progress: ResMut<Persistent<Progress>>,
+    current_level: Res<CurrentLevel>,
+    mut shake: ResMut<CameraShake>,
     mut commands: Commands,
     server: Res<AssetServer>,
 ) {
@@ -567,6 +2637,15 @@ fn check_for_coin_intersections(
                 speed_coin.respawn_duration,
                 TimerMode::Once,
             ));
+            // count it, and keep track of the best run for this level
+            // (saved with the rest of progress on the next autosave)
+            speed_coin.collected = true;
+            coin_count.0 += 1;
+            let best = progress
+                .best_coins
+                .entry(current_level.0.clone())
+                .or_default();
+            *best = (*best).max(coin_count.0);
             // play noise
             commands.spawn((
                 SamplePlayer::new(server.load("sounds/boost.wav")),
@@ -576,14 +2655,79 @@ fn check_for_coin_intersections(
                 },
             ));
             // make player go!
-            let mut boost_dir = player.0.local_z().as_vec3().normalize_or_zero() * -1.;
-            boost_dir *= COIN_BOOST;
-            player.1.0 += boost_dir;
-            player.1.0 = player.1.0.clamp_length_max(COIN_BOOST);
+            player.1.0 += coin_boost_dir(player.0) * speed_coin.boost;
+            player.1.0 = player.1.0.clamp_length_max(speed_coin.boost);
+            shake.add_trauma(COIN_SHAKE_TRAUMA);
         }
     }
 }
 
+/// Fired once when every coin in a `clear_on_all_coins` level has been grabbed
+#[derive(Event, Clone, Debug)]
+pub struct LevelCleared {
+    pub level: String,
+}
+
+// whether this level's LevelCleared already went off
+#[derive(Resource, Default)]
+struct LevelClearFired(bool);
+
+fn reset_level_clear(mut fired: ResMut<LevelClearFired>) {
+    fired.0 = false;
+}
+
+fn check_level_cleared(
+    level_tuning: Query<&LevelTuning>,
+    coins: Query<&SpeedCoin>,
+    current_level: Res<CurrentLevel>,
+    mut fired: ResMut<LevelClearFired>,
+    mut commands: Commands,
+) {
+    if fired.0 || !level_tuning.iter().any(|tuning| tuning.clear_on_all_coins) {
+        return;
+    }
+    let total = coins.iter().count();
+    let collected = coins.iter().filter(|coin| coin.collected).count();
+    if total > 0 && collected == total {
+        fired.0 = true;
+        commands.trigger(LevelCleared {
+            level: current_level.0.clone(),
+        });
+    }
+}
+
+const LEVEL_CLEARED_TEXT: &str = "<ALL COINS|every last coin|the coins are yours now|rich!!>";
+const LEVEL_CLEARED_S: f32 = 4.0;
+
+fn on_level_cleared(
+    cleared: On<LevelCleared>,
+    text_box_query: Query<Entity, With<TextBox>>,
+    server: Res<AssetServer>,
+    library: Res<DialogueLibrary>,
+    mut commands: Commands,
+) {
+    info!("level cleared: {}", cleared.level);
+    commands.spawn(SamplePlayer::new(server.load("sounds/laugh.wav")));
+    for text_box_ent in &text_box_query {
+        commands.entity(text_box_ent).despawn();
+    }
+    let context = TextContext {
+        library: Some(&library),
+        vars: None,
+    };
+    let text_box = spawn_text_box(
+        &mut commands,
+        &server,
+        &parse_random_text_with(LEVEL_CLEARED_TEXT, &context),
+    );
+    commands
+        .entity(text_box)
+        .insert(DismissAfter(Timer::from_seconds(
+            LEVEL_CLEARED_S,
+            TimerMode::Once,
+        )));
+}
+
 fn update_coin_respawn(mut coin_query: Query<(&mut SpeedCoin, &mut Visibility)>, time: Res<Time>) {
     for (mut speed_coin, mut visibility) in &mut coin_query {
         if let Some(ref mut timer) = speed_coin.respawn_timer {
@@ -598,22 +2742,284 @@ fn update_coin_respawn(mut coin_query: Query<(&mut SpeedCoin, &mut Visibility)>,
 
 const SPRITE_ROTATE_THRESHOLD: f32 = 0.0001;
 
-fn update_billboards<C: Component>(
-    camera_query: Query<&Transform, (With<Camera3d>, Without<C>)>,
-    mut sprite_query: Query<&mut Transform, (With<C>, Without<Camera3d>)>,
+/// How a billboard sprite turns to face the camera
+#[derive(Component, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BillboardMode {
+    /// Only turns around Y, so it stays standing up
+    #[default]
+    Upright,
+    /// Matches the camera's full rotation, pitch and roll included
+    Full,
+}
+
+/// Marks a sprite that turns to face the camera, see `BillboardMode` for how
+#[derive(Component, Default)]
+pub struct Billboard;
+
+fn update_billboards(
+    camera_query: Query<&Transform, (With<Camera3d>, Without<Billboard>)>,
+    mut sprite_query: Query<
+        (&mut Transform, Option<&BillboardMode>),
+        (With<Billboard>, Without<Camera3d>),
+    >,
 ) {
     let Ok(cam_tf) = camera_query.single() else {
         return;
     };
-    for mut sprite_tf in &mut sprite_query {
+    for (mut sprite_tf, mode) in &mut sprite_query {
+        let target_rotation = match mode.copied().unwrap_or_default() {
+            BillboardMode::Full => cam_tf.rotation,
+            BillboardMode::Upright => {
+                // face the camera on the horizontal plane only
+                let to_camera = cam_tf.translation - sprite_tf.translation;
+                Quat::from_rotation_y(to_camera.x.atan2(to_camera.z))
+            }
+        };
         // check diff between current sprite rotation and target
-        let current_sprite_rotation = sprite_tf.rotation.clone();
-        let target_tf = cam_tf.clone();
-        // target_tf.look_at(cam_tf.translation, Vec3::Y);
-        let target_tf_rotation = target_tf.rotation;
-        let diff = target_tf_rotation.angle_between(current_sprite_rotation);
+        let diff = target_rotation.angle_between(sprite_tf.rotation);
         if diff > SPRITE_ROTATE_THRESHOLD {
-            sprite_tf.rotation = target_tf.rotation;
+            sprite_tf.rotation = target_rotation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_parse::{parse_random_text_seeded, parse_random_text_with_vars};
+    use crate::ui::update_transition_fades;
+    use crate::{HOLE_ACTION_TEXT, LEVER_ACTION_TEXT, NPC_ACTION_TEXT};
+    use bevy::{
+        ecs::system::RunSystemOnce, scene::ScenePlugin, state::app::StatesPlugin,
+        time::TimeUpdateStrategy,
+    };
+    use rand::{SeedableRng, rngs::StdRng};
+    use std::time::Duration;
+
+    // every built in template, add new *_TEXT consts here
+    const ALL_TEXT_TEMPLATES: &[(&str, &str)] = &[
+        ("NPC_ACTION_TEXT", NPC_ACTION_TEXT),
+        ("HOLE_ACTION_TEXT", HOLE_ACTION_TEXT),
+        ("LEVER_ACTION_TEXT", LEVER_ACTION_TEXT),
+        ("DOOR_LOCKED_TEXT", DOOR_LOCKED_TEXT),
+        ("LEVEL_LOAD_FAILED_TEXT", LEVEL_LOAD_FAILED_TEXT),
+        ("LEVEL_CLEARED_TEXT", LEVEL_CLEARED_TEXT),
+    ];
+
+    #[test]
+    fn text_templates_are_well_formed() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for (name, template) in ALL_TEXT_TEMPLATES {
+            assert_eq!(validate_random_text(template), Ok(()), "{name}");
+            // `[a|b]` is valid plain text as far as the parser cares, so also check that
+            // no choice syntax makes it through to the screen
+            for _ in 0..32 {
+                let text = parse_random_text_seeded(template, &mut rng);
+                assert!(
+                    !text.contains(['|', '[', ']', '<', '>']),
+                    "{name} rendered as {text:?}"
+                );
+            }
         }
     }
+
+    #[test]
+    fn dialogue_vars_fill_in_player_state() {
+        let mut world = World::new();
+        world.insert_resource(CurrentLevel("start.map".to_string()));
+        world.insert_resource(CoinCount(3));
+        let vars = world
+            .run_system_once(|dialogue_vars: DialogueVars| dialogue_vars.get())
+            .unwrap();
+        assert_eq!(
+            parse_random_text_with_vars("{player_coins} coins in {level}", &vars),
+            "3 coins in start.map"
+        );
+    }
+
+    fn spawn_focusable(world: &mut World, focus_type: FocusType, selectable: bool) -> Entity {
+        let entity = world.spawn_empty().id();
+        world.entity_mut(entity).insert(FocusDetails {
+            entity,
+            interact_range: MAX_DIST_FOR_FOCUS,
+            name: "test".to_string(),
+            focus_type,
+            selectable,
+            text: None,
+            sound_on_action: None,
+            cursed: false,
+            action_prompt: None,
+        });
+        entity
+    }
+
+    fn listed(world: &mut World, filter: FocusFilter) -> HashSet<Entity> {
+        world
+            .run_system_once(move |focusables: Query<(Entity, &FocusDetails)>| {
+                list_focusables(&focusables, filter)
+            })
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn list_focusables_filters() {
+        let mut world = World::new();
+        let talking_rat = spawn_focusable(&mut world, FocusType::NPC, true);
+        let quiet_rat = spawn_focusable(&mut world, FocusType::NPC, false);
+        let hole = spawn_focusable(&mut world, FocusType::Hole, true);
+        let coin = spawn_focusable(&mut world, FocusType::Coin, false);
+        // not focusable at all
+        world.spawn(Transform::default());
+
+        assert_eq!(
+            listed(&mut world, FocusFilter::default()),
+            HashSet::from([talking_rat, quiet_rat, hole, coin])
+        );
+        assert_eq!(
+            listed(
+                &mut world,
+                FocusFilter {
+                    focus_type: Some(FocusType::NPC),
+                    selectable: None,
+                }
+            ),
+            HashSet::from([talking_rat, quiet_rat])
+        );
+        assert_eq!(
+            listed(
+                &mut world,
+                FocusFilter {
+                    focus_type: None,
+                    selectable: Some(true),
+                }
+            ),
+            HashSet::from([talking_rat, hole])
+        );
+        assert_eq!(
+            listed(
+                &mut world,
+                FocusFilter {
+                    focus_type: Some(FocusType::NPC),
+                    selectable: Some(true),
+                }
+            ),
+            HashSet::from([talking_rat])
+        );
+        assert!(
+            listed(
+                &mut world,
+                FocusFilter {
+                    focus_type: Some(FocusType::Door),
+                    selectable: None,
+                }
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn warping_unloads_the_level_and_starts_loading_the_next() {
+        // a snippet the old level's map defined
+        let library = DialogueLibrary(HashMap::from([(
+            "catchphrase".to_string(),
+            "squeak".to_string(),
+        )]));
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            ScenePlugin,
+            StatesPlugin,
+        ))
+        // a second a frame, so the fade is done the first frame it gets ticked
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs(1)))
+        .insert_state(GameState::InGame)
+        .insert_resource(CurrentLevel("start.map".to_string()))
+        .init_resource::<PreviousLevel>()
+        .init_resource::<PendingWarp>()
+        .init_resource::<TargetStart>()
+        .init_resource::<WarpCooldown>()
+        .insert_resource(library)
+        .add_systems(
+            Update,
+            (
+                update_transition_fades,
+                warp_when_faded.run_if(in_state(GameState::InGame)),
+            )
+                .chain(),
+        )
+        .add_systems(
+            OnTransition {
+                exited: GameState::InGame,
+                entered: GameState::Loading,
+            },
+            (reset_dialogue_library, spawn_warp_level).chain(),
+        );
+
+        // stand-in for the loaded level
+        let scene = app
+            .world_mut()
+            .resource_mut::<Assets<Scene>>()
+            .add(Scene::new(World::new()));
+        let old_level = app.world_mut().spawn(SceneRoot(scene)).id();
+        let rat = app.world_mut().spawn(LevelStuff).id();
+        app.update();
+        app.update();
+        assert!(app.world().entity(old_level).contains::<SceneInstance>());
+
+        // what clicking a hole does
+        app.world_mut()
+            .run_system_once(
+                |mut commands: Commands,
+                 mut pending_warp: ResMut<PendingWarp>,
+                 mut target_start: ResMut<TargetStart>,
+                 mut warp_cooldown: ResMut<WarpCooldown>| {
+                    start_warp(
+                        &mut commands,
+                        &mut pending_warp,
+                        &mut target_start,
+                        &mut warp_cooldown,
+                        "next.map",
+                        "door",
+                        &GameSettings::default(),
+                    );
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            app.world().resource::<PendingWarp>().0.as_deref(),
+            Some("next.map")
+        );
+
+        // fade goes black and the swap happens, then the state change lands
+        app.update();
+        app.update();
+
+        assert!(app.world().get_entity(rat).is_err());
+        assert!(app.world().get_entity(old_level).is_err());
+        assert_eq!(
+            *app.world().resource::<State<GameState>>().get(),
+            GameState::Loading
+        );
+        assert_eq!(app.world().resource::<CurrentLevel>().0, "next.map");
+        assert_eq!(
+            app.world().resource::<PreviousLevel>().0.as_deref(),
+            Some("start.map")
+        );
+        assert_eq!(
+            app.world().resource::<TargetStart>().0.as_deref(),
+            Some("door")
+        );
+        assert!(app.world().resource::<PendingWarp>().0.is_none());
+        assert!(app.world().resource::<DialogueLibrary>().0.is_empty());
+        let new_scenes: Vec<_> = app
+            .world_mut()
+            .query::<&SceneRoot>()
+            .iter(app.world())
+            .filter_map(|root| root.0.path().map(|path| path.to_string()))
+            .collect();
+        assert_eq!(new_scenes, vec![level_scene_path("next.map")]);
+    }
 }
@@ -1,104 +1,973 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
 use rand::Rng;
+use std::{collections::HashMap, fmt};
+
+/// Maximum nesting depth for template expansion. Past this, the remaining text is
+/// emitted unexpanded so a self-referential template can't recurse forever.
+pub const MAX_PARSE_DEPTH: usize = 32;
 
 /// Parses a text string with random substitutions, weighted probabilities, and escape sequences.
 ///
 /// Syntax:
 /// - `<option1|option2|option3>` - random choice
-/// - `<common:70|rare:30>` - weighted choice (weights are relative)
+/// - `<common:70|rare:30>` - weighted choice (weights are relative, and can be fractional
+///   like `<rare:0.5|common:9.5>`). A weight of 0 is never picked, unless every option
+///   has one, then they're all even
+/// - `<upper:text>`, `<lower:text>`, `<title:text>` - changes the case of `text` after
+///   expanding it, so `<upper:<hi|hey>>` works
+/// - `<~ a | b | c>` - a leading `~` trims the whitespace around each option, without
+///   it spaces are kept exactly as written
+/// - `<<x|y>|z>` - options can hold their own choices, the picked option gets expanded
+///   again (up to `MAX_PARSE_DEPTH` levels)
+/// - `<1-20>` - random integer in the range, inclusive (negatives like `<-5--1>` work, and
+///   reversed ranges get swapped)
 /// - `\<` and `\>` - escaped angle brackets (literal < and >)
 /// - `\|` - escaped pipe (literal | inside options)
+/// - `@name` - shared snippet from a `DialogueLibrary` (see `parse_random_text_with_library`)
+/// - `\@` - escaped at sign (literal @)
+/// - `{name}` - variable from the caller (see `parse_random_text_with_vars`), unknown ones
+///   expand to nothing
+/// - `\{` and `\}` - escaped braces (literal { and })
+/// - `\\` - escaped backslash (literal \)
+///
+/// Anything malformed is echoed rather than dropped: a backslash before any other
+/// character (or at the very end) stays in the text, and an unclosed `<` comes out
+/// as written with its contents still expanded.
 pub fn parse_random_text(input: &str) -> String {
-    let mut result = String::new();
+    parse_random_text_seeded(input, &mut rand::rng())
+}
+
+/// Same as `parse_random_text`, but picks using the caller's `rng`, so a seeded rng
+/// always gives the same text
+pub fn parse_random_text_seeded(input: &str, rng: &mut impl Rng) -> String {
+    expand_text(input, rng, &TextContext::default(), 0)
+}
+
+/// Problems `parse_random_text_checked` can find in a template, offsets are in bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextParseError {
+    /// A `<` that never gets closed
+    UnclosedBracket { offset: usize },
+    /// A `>` with no `<` before it, escape it as `\>` if it's meant to show up
+    UnmatchedClose { offset: usize },
+    /// `<>` or an empty option like the middle one in `<a||b>`
+    EmptyOption { offset: usize },
+    /// Every option in a choice has a weight of 0 (or less)
+    ZeroTotalWeight { offset: usize },
+}
+
+impl fmt::Display for TextParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextParseError::UnclosedBracket { offset } => {
+                write!(f, "unclosed '<' at byte {offset}")
+            }
+            TextParseError::UnmatchedClose { offset } => {
+                write!(f, "'>' with no matching '<' at byte {offset}")
+            }
+            TextParseError::EmptyOption { offset } => write!(f, "empty option at byte {offset}"),
+            TextParseError::ZeroTotalWeight { offset } => {
+                write!(f, "all options weigh 0 in choice at byte {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TextParseError {}
+
+/// Strict version of `parse_random_text` for checking authored text, returns the first
+/// problem found instead of echoing malformed tags
+pub fn parse_random_text_checked(input: &str) -> Result<String, TextParseError> {
+    validate_random_text(input)?;
+    Ok(parse_random_text(input))
+}
+
+/// Checks a template for malformed tags without expanding it. Like expansion, anything
+/// nested past `MAX_PARSE_DEPTH` is left alone.
+pub fn validate_random_text(input: &str) -> Result<(), TextParseError> {
+    validate_at(input, 0, 0)
+}
+
+fn validate_at(input: &str, base_offset: usize, depth: usize) -> Result<(), TextParseError> {
+    if depth > MAX_PARSE_DEPTH {
+        return Ok(());
+    }
+    let mut chars = input.char_indices();
+
+    while let Some((i, ch)) = chars.next() {
+        if ch == '\\' {
+            chars.next();
+        } else if ch == '<' {
+            let offset = base_offset + i;
+            let body_start = i + 1;
+            let mut body_end = None;
+            let mut nesting = 0;
+
+            while let Some((j, next_ch)) = chars.next() {
+                if next_ch == '\\' {
+                    chars.next();
+                } else if next_ch == '<' {
+                    nesting += 1;
+                } else if next_ch == '>' {
+                    if nesting == 0 {
+                        body_end = Some(j);
+                        break;
+                    }
+                    nesting -= 1;
+                }
+            }
+
+            let Some(body_end) = body_end else {
+                return Err(TextParseError::UnclosedBracket { offset });
+            };
+            validate_options(
+                &input[body_start..body_end],
+                base_offset + body_start,
+                depth,
+            )?;
+        } else if ch == '>' {
+            return Err(TextParseError::UnmatchedClose {
+                offset: base_offset + i,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_options(
+    options_str: &str,
+    base_offset: usize,
+    depth: usize,
+) -> Result<(), TextParseError> {
+    if let Some((_, rest)) = split_case_transform(options_str) {
+        let rest_offset = base_offset + (options_str.len() - rest.len());
+        return validate_at(rest, rest_offset, depth + 1);
+    }
+    let (options_str, trim) = split_trim_prefix(options_str);
+    if parse_range(options_str).is_some() {
+        return Ok(());
+    }
+
+    let mut part_offset = if trim {
+        base_offset + TRIM_OPTIONS_PREFIX.len_utf8()
+    } else {
+        base_offset
+    };
+    let mut all_zero_weight = true;
+    for part in split_options(options_str) {
+        if part.is_empty() || (trim && part.trim().is_empty()) {
+            return Err(TextParseError::EmptyOption {
+                offset: part_offset,
+            });
+        }
+        match split_weight(part) {
+            Some((text, weight)) => {
+                all_zero_weight &= weight <= 0.0;
+                validate_at(text, part_offset, depth + 1)?;
+            }
+            None => {
+                all_zero_weight = false;
+                validate_at(part, part_offset, depth + 1)?;
+            }
+        }
+        part_offset += part.len() + 1;
+    }
+
+    if all_zero_weight {
+        // point at the opening '<'
+        return Err(TextParseError::ZeroTotalWeight {
+            offset: base_offset - 1,
+        });
+    }
+
+    Ok(())
+}
+
+/// Named dialogue snippets that templates can pull in with `@name`
+#[derive(Resource, Default, Clone, Debug)]
+pub struct DialogueLibrary(pub HashMap<String, String>);
+
+/// Compiled templates keyed by their source text, see `DialogueTemplates`
+#[derive(Resource, Default)]
+pub struct TemplateCache(HashMap<String, CompiledTemplate>);
+
+/// For systems that render the same templates over and over: compiles each source string
+/// the first time it's seen and renders it against the `DialogueLibrary` after that
+#[derive(SystemParam)]
+pub struct DialogueTemplates<'w> {
+    library: Res<'w, DialogueLibrary>,
+    cache: ResMut<'w, TemplateCache>,
+}
+
+impl DialogueTemplates<'_> {
+    /// Like `parse_random_text_with`, with the library filled in and `{name}`s from `vars`
+    pub fn render(&mut self, input: &str, vars: Option<&HashMap<String, String>>) -> String {
+        let context = TextContext {
+            library: Some(&*self.library),
+            vars,
+        };
+        self.cache
+            .0
+            .entry(input.to_string())
+            .or_insert_with(|| compile(input))
+            .render_with(&mut rand::rng(), &context)
+    }
+}
+
+/// Same as `parse_random_text`, but `@name` references get expanded from `library`.
+/// Unknown references are left in the text as-is.
+pub fn parse_random_text_with_library(input: &str, library: &DialogueLibrary) -> String {
+    parse_random_text_with(
+        input,
+        &TextContext {
+            library: Some(library),
+            ..default()
+        },
+    )
+}
+
+/// Same as `parse_random_text`, but `{name}` placeholders get filled in from `vars`
+pub fn parse_random_text_with_vars(input: &str, vars: &HashMap<String, String>) -> String {
+    parse_random_text_with(
+        input,
+        &TextContext {
+            vars: Some(vars),
+            ..default()
+        },
+    )
+}
+
+/// Everything outside the template itself that expansion can pull from
+#[derive(Default, Clone, Copy)]
+pub struct TextContext<'a> {
+    pub library: Option<&'a DialogueLibrary>,
+    pub vars: Option<&'a HashMap<String, String>>,
+}
+
+/// Same as `parse_random_text`, expanding `@name` and `{name}` from `context`
+pub fn parse_random_text_with(input: &str, context: &TextContext) -> String {
+    expand_text(input, &mut rand::rng(), context, 0)
+}
+
+/// Expands `input` at the given nesting `depth`, bailing out with the raw text once
+/// `MAX_PARSE_DEPTH` is exceeded.
+fn expand_text<R: Rng>(input: &str, rng: &mut R, context: &TextContext, depth: usize) -> String {
+    compile_at(input, depth).render_with(rng, context)
+}
+
+/// A template parsed once into literals and choices, so text that gets shown over and
+/// over (NPC dialogue) doesn't get re-scanned every time. Renders the same as
+/// `parse_random_text_with` would on the source string.
+#[derive(Clone, Debug)]
+pub struct CompiledTemplate(Vec<TemplateNode>);
+
+#[derive(Clone, Debug)]
+enum TemplateNode {
+    Literal(String),
+    /// `<a:2|b>`, options are already compiled and picked between by weight
+    Choice(Vec<(CompiledTemplate, f32)>),
+    /// `<1-20>`
+    Range(i64, i64),
+    /// `@name`, looked up at render time since the library can change. Keeps the depth
+    /// it showed up at so self-referencing snippets still hit `MAX_PARSE_DEPTH`
+    Snippet {
+        name: String,
+        depth: usize,
+    },
+    /// `{name}`
+    Var(String),
+    /// `<upper:...>` and friends
+    Case(CaseTransform, CompiledTemplate),
+}
+
+/// Case change a `<upper:...>` style tag applies to its expanded text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CaseTransform {
+    Upper,
+    Lower,
+    Title,
+}
+
+impl CaseTransform {
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "upper" => Some(CaseTransform::Upper),
+            "lower" => Some(CaseTransform::Lower),
+            "title" => Some(CaseTransform::Title),
+            _ => None,
+        }
+    }
+
+    fn apply(self, text: &str) -> String {
+        match self {
+            CaseTransform::Upper => text.to_uppercase(),
+            CaseTransform::Lower => text.to_lowercase(),
+            CaseTransform::Title => {
+                // capitalize the start of each word, lowercase the rest
+                let mut result = String::with_capacity(text.len());
+                let mut word_start = true;
+                for ch in text.chars() {
+                    if word_start {
+                        result.extend(ch.to_uppercase());
+                    } else {
+                        result.extend(ch.to_lowercase());
+                    }
+                    word_start = ch.is_whitespace();
+                }
+                result
+            }
+        }
+    }
+}
+
+/// Splits a `tag:rest` options string if `tag` is a known case transform. Unknown tags
+/// return None, so things like `<a:2|b>` stay weighted choices
+fn split_case_transform(options_str: &str) -> Option<(CaseTransform, &str)> {
+    let (tag, rest) = options_str.split_once(':')?;
+    Some((CaseTransform::from_tag(tag)?, rest))
+}
+
+/// Parses `input` into a `CompiledTemplate`
+pub fn compile(input: &str) -> CompiledTemplate {
+    compile_at(input, 0)
+}
+
+impl CompiledTemplate {
+    /// Renders with no library or variables
+    pub fn render(&self, rng: &mut impl Rng) -> String {
+        self.render_with(rng, &TextContext::default())
+    }
+
+    /// Renders, expanding `@name` and `{name}` from `context`
+    pub fn render_with(&self, rng: &mut impl Rng, context: &TextContext) -> String {
+        let mut result = String::new();
+        self.render_into(&mut result, rng, context);
+        result
+    }
+
+    fn render_into<R: Rng>(&self, result: &mut String, rng: &mut R, context: &TextContext) {
+        for node in &self.0 {
+            match node {
+                TemplateNode::Literal(text) => result.push_str(text),
+                TemplateNode::Choice(options) => {
+                    pick_weighted(options, rng).render_into(result, rng, context)
+                }
+                TemplateNode::Range(min, max) => {
+                    result.push_str(&rng.random_range(*min..=*max).to_string())
+                }
+                TemplateNode::Snippet { name, depth } => {
+                    match context.library.and_then(|library| library.0.get(name)) {
+                        Some(snippet) => {
+                            result.push_str(&expand_text(snippet, rng, context, depth + 1))
+                        }
+                        None => {
+                            // Unknown (or no library), leave the reference as written
+                            result.push('@');
+                            result.push_str(name);
+                        }
+                    }
+                }
+                TemplateNode::Var(name) => {
+                    // Unknown variables come out empty
+                    if let Some(value) = context.vars.and_then(|vars| vars.get(name)) {
+                        result.push_str(value);
+                    }
+                }
+                TemplateNode::Case(transform, inner) => {
+                    result.push_str(&transform.apply(&inner.render_with(rng, context)))
+                }
+            }
+        }
+    }
+}
+
+/// Builds up a template's nodes, merging runs of plain text into single literals
+#[derive(Default)]
+struct TemplateBuilder {
+    nodes: Vec<TemplateNode>,
+    literal: String,
+}
+
+impl TemplateBuilder {
+    fn push_char(&mut self, ch: char) {
+        self.literal.push(ch);
+    }
+
+    fn push_str(&mut self, text: &str) {
+        self.literal.push_str(text);
+    }
+
+    fn push_node(&mut self, node: TemplateNode) {
+        self.flush();
+        self.nodes.push(node);
+    }
+
+    fn append(&mut self, template: CompiledTemplate) {
+        for node in template.0 {
+            match node {
+                TemplateNode::Literal(text) => self.push_str(&text),
+                node => self.push_node(node),
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.literal.is_empty() {
+            self.nodes
+                .push(TemplateNode::Literal(std::mem::take(&mut self.literal)));
+        }
+    }
+
+    fn finish(mut self) -> CompiledTemplate {
+        self.flush();
+        CompiledTemplate(self.nodes)
+    }
+}
+
+/// Compiles `input` at the given nesting `depth`, leaving it as raw text once
+/// `MAX_PARSE_DEPTH` is exceeded.
+fn compile_at(input: &str, depth: usize) -> CompiledTemplate {
+    if depth > MAX_PARSE_DEPTH {
+        log::warn!(
+            "text template exceeded max depth of {MAX_PARSE_DEPTH}, leaving unexpanded: {input}"
+        );
+        return CompiledTemplate(vec![TemplateNode::Literal(input.to_string())]);
+    }
+
+    let mut builder = TemplateBuilder::default();
     let mut chars = input.chars().peekable();
-    let mut rng = rand::rng();
 
     while let Some(ch) = chars.next() {
         if ch == '\\' {
             // Handle escape sequences
             if let Some(&next_ch) = chars.peek() {
-                if next_ch == '<' || next_ch == '>' || next_ch == '|' || next_ch == '\\' {
-                    result.push(chars.next().unwrap());
+                if next_ch == '<'
+                    || next_ch == '>'
+                    || next_ch == '|'
+                    || next_ch == '\\'
+                    || next_ch == '@'
+                    || next_ch == '{'
+                    || next_ch == '}'
+                {
+                    builder.push_char(chars.next().unwrap());
                     continue;
                 }
             }
             // Not a recognized escape, just include the backslash
-            result.push(ch);
+            builder.push_char(ch);
         } else if ch == '<' {
-            // Collect everything until we find the closing '>'
+            // Collect everything until the matching '>', leaving escapes in place so
+            // the options can be compiled on their own
             let mut options_str = String::new();
             let mut found_closing = false;
-            let mut escape_next = false;
+            let mut nesting = 0;
 
-            while let Some(&next_ch) = chars.peek() {
-                if escape_next {
-                    options_str.push(chars.next().unwrap());
-                    escape_next = false;
-                } else if next_ch == '\\' {
-                    chars.next(); // consume the backslash
-                    escape_next = true;
+            while let Some(next_ch) = chars.next() {
+                if next_ch == '\\' {
+                    options_str.push(next_ch);
+                    if let Some(escaped) = chars.next() {
+                        options_str.push(escaped);
+                    }
+                    continue;
+                } else if next_ch == '<' {
+                    nesting += 1;
                 } else if next_ch == '>' {
-                    chars.next(); // consume the '>'
+                    if nesting == 0 {
+                        found_closing = true;
+                        break;
+                    }
+                    nesting -= 1;
+                }
+                options_str.push(next_ch);
+            }
+
+            let (trimmed_options, trim) = split_trim_prefix(&options_str);
+            if found_closing && let Some((transform, rest)) = split_case_transform(&options_str) {
+                // Case change over whatever the rest expands to
+                builder.push_node(TemplateNode::Case(transform, compile_at(rest, depth + 1)));
+            } else if found_closing && let Some((min, max)) = parse_range(trimmed_options) {
+                // Numeric range, pick an integer in it
+                builder.push_node(TemplateNode::Range(min, max));
+            } else if found_closing && !trimmed_options.is_empty() {
+                // Options with optional weights, each compiled for whenever it gets picked
+                builder.push_node(TemplateNode::Choice(compile_options(
+                    trimmed_options,
+                    trim,
+                    depth,
+                )));
+            } else {
+                // Malformed pattern, just include the '<' and what we collected
+                builder.push_char('<');
+                builder.append(compile_at(&options_str, depth + 1));
+            }
+        } else if ch == '@' {
+            // Collect the snippet name
+            let mut name = String::new();
+            while let Some(&next_ch) = chars.peek() {
+                if next_ch.is_alphanumeric() || next_ch == '_' {
+                    name.push(chars.next().unwrap());
+                } else {
+                    break;
+                }
+            }
+            builder.push_node(TemplateNode::Snippet { name, depth });
+        } else if ch == '{' {
+            // Collect the variable name
+            let mut name = String::new();
+            let mut found_closing = false;
+            for next_ch in chars.by_ref() {
+                if next_ch == '}' {
                     found_closing = true;
                     break;
-                } else {
-                    options_str.push(chars.next().unwrap());
                 }
+                name.push(next_ch);
             }
 
-            if found_closing && !options_str.is_empty() {
-                // Parse options with optional weights
-                let choice = select_weighted_option(&options_str, &mut rng);
-                result.push_str(&choice);
+            if found_closing {
+                builder.push_node(TemplateNode::Var(name.trim().to_string()));
             } else {
-                // Malformed pattern, just include the '<' and what we collected
-                result.push('<');
-                result.push_str(&options_str);
+                // Malformed, just include what we collected
+                builder.push_char('{');
+                builder.push_str(&name);
             }
         } else {
-            result.push(ch);
+            builder.push_char(ch);
         }
     }
 
-    result
+    builder.finish()
 }
 
-/// Selects a weighted option from a string like "option1:weight1|option2:weight2|option3"
-fn select_weighted_option<R: Rng>(options_str: &str, rng: &mut R) -> String {
-    let mut options = Vec::new();
-    let mut weights = Vec::new();
+/// Parses a `min-max` range token, where either end can be negative. Bounds come back
+/// in order, so `5-3` gives `(3, 5)`
+fn parse_range(token: &str) -> Option<(i64, i64)> {
+    // skip the first char so a leading minus isn't taken as the separator
+    let (sep, _) = token.char_indices().skip(1).find(|&(_, ch)| ch == '-')?;
+    let (min_str, max_str) = (&token[..sep], &token[sep + 1..]);
 
-    for part in options_str.split('|') {
-        if let Some((text, weight_str)) = part.rsplit_once(':') {
-            // Try to parse weight
-            if let Ok(weight) = weight_str.trim().parse::<u32>() {
-                if weight > 0 {
-                    options.push(text.to_string());
-                    weights.push(weight);
-                    continue;
-                }
-            }
-        }
-        // No weight or invalid weight, default to weight of 1
-        options.push(part.to_string());
-        weights.push(1);
+    let is_integer = |s: &str| {
+        let digits = s.strip_prefix('-').unwrap_or(s);
+        !digits.is_empty() && digits.chars().all(|ch| ch.is_ascii_digit())
+    };
+    if !is_integer(min_str) || !is_integer(max_str) {
+        return None;
+    }
+
+    let min = min_str.parse::<i64>().ok()?;
+    let max = max_str.parse::<i64>().ok()?;
+    Some((min.min(max), min.max(max)))
+}
+
+/// Marks a choice whose options get their surrounding whitespace trimmed, like `<~ a | b>`
+const TRIM_OPTIONS_PREFIX: char = '~';
+
+/// Strips a leading `TRIM_OPTIONS_PREFIX` off an options string, returning whether it was there
+fn split_trim_prefix(options_str: &str) -> (&str, bool) {
+    match options_str.strip_prefix(TRIM_OPTIONS_PREFIX) {
+        Some(rest) => (rest, true),
+        None => (options_str, false),
     }
+}
 
-    if options.is_empty() {
-        return String::new();
+/// Splits an options string on its top level `|`s, skipping escaped pipes and any
+/// inside nested `<...>`
+fn split_options(options_str: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut nesting = 0;
+    let mut escape_next = false;
+
+    for (i, ch) in options_str.char_indices() {
+        if escape_next {
+            escape_next = false;
+        } else if ch == '\\' {
+            escape_next = true;
+        } else if ch == '<' {
+            nesting += 1;
+        } else if ch == '>' && nesting > 0 {
+            nesting -= 1;
+        } else if ch == '|' && nesting == 0 {
+            parts.push(&options_str[start..i]);
+            start = i + 1;
+        }
     }
+    parts.push(&options_str[start..]);
+
+    parts
+}
+
+/// Splits an `option:weight` part into its text and weight, if it has a weight
+fn split_weight(part: &str) -> Option<(&str, f32)> {
+    let (text, weight_str) = part.rsplit_once(':')?;
+    let weight = weight_str.trim().parse::<f32>().ok()?;
+    weight.is_finite().then_some((text, weight))
+}
+
+/// Compiles each option in a string like "option1:weight1|option2:weight2|option3",
+/// with `trim` taking the whitespace off around each one
+fn compile_options(options_str: &str, trim: bool, depth: usize) -> Vec<(CompiledTemplate, f32)> {
+    let tidy = |text: &str| {
+        if trim {
+            compile_at(text.trim(), depth + 1)
+        } else {
+            compile_at(text, depth + 1)
+        }
+    };
+
+    split_options(options_str)
+        .into_iter()
+        .map(|part| match split_weight(part) {
+            // 0 (or less) keeps the option around but it never gets picked
+            Some((text, weight)) => (tidy(text), weight.max(0.0)),
+            // No weight or invalid weight, default to weight of 1
+            None => (tidy(part), 1.0),
+        })
+        .collect()
+}
 
-    // Calculate total weight
-    let total_weight: u32 = weights.iter().sum();
-    let mut roll = rng.random_range(0..total_weight);
+/// Picks one of `options` with probability proportional to its weight
+fn pick_weighted<'a, T, R: Rng>(options: &'a [(T, f32)], rng: &mut R) -> &'a T {
+    let total_weight: f32 = options.iter().map(|(_, weight)| weight).sum();
+    if total_weight <= 0.0 || !total_weight.is_finite() {
+        // nothing to go on (or weights too big to add up), even odds
+        return &options[rng.random_range(0..options.len())].0;
+    }
+    let mut roll = rng.random_range(0.0..total_weight);
 
     // Select based on weighted probability
-    for (i, &weight) in weights.iter().enumerate() {
-        if roll < weight {
-            return options[i].clone();
+    for (option, weight) in options {
+        if roll < *weight {
+            return option;
         }
         roll -= weight;
     }
 
-    // Fallback (shouldn't reach here)
-    options[0].clone()
+    // Float rounding can leave the roll just past the end, that belongs to the last option
+    // that could have been picked
+    options
+        .iter()
+        .rev()
+        .find(|(_, weight)| *weight > 0.0)
+        .map(|(option, _)| option)
+        .unwrap_or(&options[options.len() - 1].0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+    use std::collections::HashSet;
+
+    const SEED: u64 = 7;
+
+    fn parse(input: &str) -> String {
+        parse_random_text_seeded(input, &mut StdRng::seed_from_u64(SEED))
+    }
+
+    /// Every distinct output of `input` over a bunch of seeded renders
+    fn outcomes(input: &str) -> HashSet<String> {
+        let mut rng = StdRng::seed_from_u64(SEED);
+        (0..256)
+            .map(|_| parse_random_text_seeded(input, &mut rng))
+            .collect()
+    }
+
+    fn set(items: &[&str]) -> HashSet<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn text_past_the_depth_cap_is_left_unexpanded() {
+        let mut rng = rand::rng();
+        let mut expand =
+            |input: &str, depth| expand_text(input, &mut rng, &TextContext::default(), depth);
+        assert_eq!(expand("<only>", MAX_PARSE_DEPTH), "only");
+        assert_eq!(expand("<a|b>", MAX_PARSE_DEPTH + 1), "<a|b>");
+    }
+
+    /// `x` wrapped in `levels` pairs of brackets
+    fn nested_x(levels: usize) -> String {
+        format!("{}x{}", "<".repeat(levels), ">".repeat(levels))
+    }
+
+    #[test]
+    fn deep_nesting_terminates() {
+        // way past MAX_PARSE_DEPTH, would blow the stack without the cap
+        let levels = 10_000;
+        // each level expanded peels off one pair, then the rest comes out as written
+        let left = levels - (MAX_PARSE_DEPTH + 1);
+        assert_eq!(parse(&nested_x(levels)), nested_x(left));
+    }
+
+    fn library(snippets: &[(&str, &str)]) -> DialogueLibrary {
+        DialogueLibrary(
+            snippets
+                .iter()
+                .map(|(name, text)| (name.to_string(), text.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn snippet_references_expand() {
+        let library = library(&[("greeting", "hello"), ("catchphrase", "@greeting, rat")]);
+        assert_eq!(
+            parse_random_text_with_library("@greeting!", &library),
+            "hello!"
+        );
+        // snippets can pull in other snippets
+        assert_eq!(
+            parse_random_text_with_library("@catchphrase", &library),
+            "hello, rat"
+        );
+        assert_eq!(
+            parse_random_text_with_library(r"\@greeting", &library),
+            "@greeting"
+        );
+    }
+
+    #[test]
+    fn undefined_snippet_references_are_left_as_written() {
+        let library = library(&[("greeting", "hello")]);
+        assert_eq!(
+            parse_random_text_with_library("@nobody home", &library),
+            "@nobody home"
+        );
+        // no library at all
+        assert_eq!(parse_random_text("@greeting"), "@greeting");
+    }
+
+    #[test]
+    fn self_referencing_snippet_terminates() {
+        let library = library(&[("loop", "again @loop")]);
+        let text = parse_random_text_with_library("@loop", &library);
+        assert_eq!(
+            text,
+            format!("{}@loop", "again ".repeat(MAX_PARSE_DEPTH + 1))
+        );
+    }
+
+    #[test]
+    fn nested_choices_expand() {
+        assert_eq!(outcomes("<<x|y>|z>"), set(&["x", "y", "z"]));
+        assert_eq!(parse("<<<deep>>>"), "deep");
+        assert_eq!(outcomes("<a <b|c> d|e>"), set(&["a b d", "a c d", "e"]));
+        assert_eq!(
+            outcomes("<<<1|2>|3>|<4|<5|6>>>"),
+            set(&["1", "2", "3", "4", "5", "6"])
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse("<1-1>"), "1");
+        // reversed bounds get swapped
+        assert_eq!(outcomes("<5-3>"), set(&["3", "4", "5"]));
+        assert_eq!(outcomes("<-2-2>"), set(&["-2", "-1", "0", "1", "2"]));
+        assert_eq!(outcomes("<-5--3>"), set(&["-5", "-4", "-3"]));
+    }
+
+    #[test]
+    fn malformed_ranges_are_plain_options() {
+        assert_eq!(parse("<1->"), "1-");
+        assert_eq!(parse("<-1>"), "-1");
+        assert_eq!(parse("<a-b>"), "a-b");
+        assert_eq!(parse("<1-2-3>"), "1-2-3");
+        assert_eq!(parse("<1.5-2>"), "1.5-2");
+        // too big for an i64
+        assert_eq!(parse("<1-99999999999999999999>"), "1-99999999999999999999");
+    }
+
+    #[test]
+    fn same_seed_same_text() {
+        let input = "<a|b|c> <1-100> <<x|y>|z:3>";
+        let first = parse_random_text_seeded(input, &mut StdRng::seed_from_u64(SEED));
+        let again = parse_random_text_seeded(input, &mut StdRng::seed_from_u64(SEED));
+        assert_eq!(first, again);
+    }
+
+    #[test]
+    fn balanced_templates_validate() {
+        for input in [
+            "plain text",
+            "<a|b> and <c:2|<d|e>>",
+            "<1-5> teeth",
+            "<upper:<hi|hey>>",
+            "<~ a | b >",
+            r"\<not a tag\> a\|b",
+        ] {
+            assert_eq!(validate_random_text(input), Ok(()), "{input}");
+        }
+    }
+
+    #[test]
+    fn unclosed_brackets_are_reported_where_they_open() {
+        assert_eq!(
+            validate_random_text("hi <there"),
+            Err(TextParseError::UnclosedBracket { offset: 3 })
+        );
+        // the inner one closes, the outer one doesn't
+        assert_eq!(
+            validate_random_text("<a|<b>"),
+            Err(TextParseError::UnclosedBracket { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn stray_closing_brackets_are_reported() {
+        assert_eq!(
+            validate_random_text("a|b>"),
+            Err(TextParseError::UnmatchedClose { offset: 3 })
+        );
+        assert_eq!(
+            validate_random_text("<a> b>"),
+            Err(TextParseError::UnmatchedClose { offset: 5 })
+        );
+        assert_eq!(validate_random_text(r"a\>b"), Ok(()));
+        // lenient parsing just echoes it
+        assert_eq!(parse("a|b>"), "a|b>");
+    }
+
+    #[test]
+    fn option_errors_point_at_the_option() {
+        assert_eq!(
+            validate_random_text("<>"),
+            Err(TextParseError::EmptyOption { offset: 1 })
+        );
+        assert_eq!(
+            validate_random_text("<a||b>"),
+            Err(TextParseError::EmptyOption { offset: 3 })
+        );
+        assert_eq!(
+            validate_random_text("<a|<b||c>>"),
+            Err(TextParseError::EmptyOption { offset: 6 })
+        );
+        // zero weight points at the choice's '<'
+        assert_eq!(
+            validate_random_text("xy<a:0|b:0>"),
+            Err(TextParseError::ZeroTotalWeight { offset: 2 })
+        );
+    }
+
+    #[test]
+    fn checked_parse_returns_text_or_the_first_error() {
+        assert_eq!(parse_random_text_checked("<only>"), Ok("only".to_string()));
+        let err = parse_random_text_checked("hi <there").unwrap_err();
+        assert_eq!(err, TextParseError::UnclosedBracket { offset: 3 });
+        assert_eq!(err.to_string(), "unclosed '<' at byte 3");
+    }
+
+    #[test]
+    fn validation_stops_at_the_depth_cap() {
+        assert_eq!(validate_random_text(&nested_x(10_000)), Ok(()));
+    }
+
+    /// How often `option` comes out of `input` over many seeded renders
+    fn share_of(input: &str, option: &str) -> f32 {
+        let samples = 20_000;
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let hits = (0..samples)
+            .filter(|_| parse_random_text_seeded(input, &mut rng) == option)
+            .count();
+        hits as f32 / samples as f32
+    }
+
+    #[test]
+    fn fractional_weights_match_their_odds() {
+        let rare = share_of("<rare:0.5|common:9.5>", "rare");
+        assert!((rare - 0.05).abs() < 0.01, "rare came up {rare}");
+        let b = share_of("<a:0.25|b:0.5|c:0.25>", "b");
+        assert!((b - 0.5).abs() < 0.02, "b came up {b}");
+        // whole numbers and unweighted options still mix
+        let a = share_of("<a:3|b>", "a");
+        assert!((a - 0.75).abs() < 0.02, "a came up {a}");
+    }
+
+    #[test]
+    fn zero_weights_are_never_picked() {
+        assert_eq!(outcomes("<never:0|always>"), set(&["always"]));
+        assert_eq!(outcomes("<a:0|b:0.5|c:0>"), set(&["b"]));
+        let b = share_of("<a:0|b:0.5|c:1.5>", "b");
+        assert!((b - 0.25).abs() < 0.02, "b came up {b}");
+        // all zero has nothing to go on, so it's even odds
+        let a = share_of("<a:0|b:0>", "a");
+        assert!((a - 0.5).abs() < 0.02, "a came up {a}");
+    }
+
+    #[test]
+    fn weights_too_big_to_add_up_dont_panic() {
+        assert_eq!(outcomes("<a:3e38|b:3e38>"), set(&["a", "b"]));
+        assert_eq!(validate_random_text("<a:3e38|b:3e38>"), Ok(()));
+    }
+
+    #[test]
+    fn escaped_brackets_are_literal() {
+        assert_eq!(parse(r"\<hi\>"), "<hi>");
+        assert_eq!(parse(r"a \<b|c\> d"), "a <b|c> d");
+        assert_eq!(parse(r"back\\slash"), r"back\slash");
+    }
+
+    #[test]
+    fn escaped_pipe_stays_in_its_option() {
+        assert_eq!(parse(r"<a\|b>"), "a|b");
+        let mut rng = StdRng::seed_from_u64(SEED);
+        for _ in 0..64 {
+            let text = parse_random_text_seeded(r"<a\|b|c>", &mut rng);
+            assert!(text == "a|b" || text == "c", "got {text:?}");
+        }
+    }
+
+    #[test]
+    fn stray_backslashes_are_kept() {
+        assert_eq!(parse(r"trailing\"), r"trailing\");
+        assert_eq!(parse(r"not\nan escape"), r"not\nan escape");
+    }
+
+    #[test]
+    fn unclosed_bracket_is_echoed() {
+        assert_eq!(parse("hi <there"), "hi <there");
+        assert_eq!(parse("<a|b"), "<a|b");
+        // the contents still get expanded
+        assert_eq!(parse("<<x>"), "<x");
+        assert_eq!(parse(r"oops <\@x"), "oops <@x");
+    }
+
+    #[test]
+    fn trim_prefix_drops_spacing_around_options() {
+        // spacing is kept exactly as written by default
+        assert_eq!(outcomes("[< a | b >]"), set(&["[ a ]", "[ b ]"]));
+        assert_eq!(outcomes("[<~ a | b >]"), set(&["[a]", "[b]"]));
+        // only around each option, not inside it
+        assert_eq!(parse("<~  two words  >"), "two words");
+        assert_eq!(outcomes("<~ a:3 | b >"), set(&["a", "b"]));
+    }
+
+    #[test]
+    fn inner_brackets_dont_close_the_outer_choice() {
+        assert_eq!(parse("<outer <inner> tail>"), "outer inner tail");
+        assert_eq!(outcomes("<a <b|c> d|e>"), set(&["a b d", "a c d", "e"]));
+        // one `>` short, so the outer one never closes
+        assert_eq!(parse("<outer <inner tail>"), "<outer inner tail");
+    }
+
+    #[test]
+    fn case_tags() {
+        assert_eq!(parse("<upper:talk>"), "TALK");
+        assert_eq!(parse("<lower:QUIET Please>"), "quiet please");
+        assert_eq!(parse("<title:the BIG rat>"), "The Big Rat");
+        // unknown tags are still weighted choices
+        assert_eq!(parse("<shout:1>"), "shout");
+    }
+
+    #[test]
+    fn case_tags_apply_after_nested_choices() {
+        assert_eq!(outcomes("<upper:<hi|hey>>"), set(&["HI", "HEY"]));
+        assert_eq!(
+            outcomes("<title:<big|small> <rat|mouse>>"),
+            set(&["Big Rat", "Big Mouse", "Small Rat", "Small Mouse"])
+        );
+        assert_eq!(outcomes("<lower:<A|<B|C>>>"), set(&["a", "b", "c"]));
+        assert_eq!(parse("<upper:<1-1> rat>"), "1 RAT");
+    }
 }
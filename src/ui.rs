@@ -1,4 +1,7 @@
+use avian3d::prelude::{LinearVelocity, Physics, PhysicsTime};
 use bevy::{
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    input::common_conditions::input_just_pressed,
     input_focus::{
         InputDispatchPlugin,
         tab_navigation::{TabIndex, TabNavigationPlugin},
@@ -9,14 +12,20 @@ use bevy::{
         CoreSliderDragState, Slider, SliderRange, SliderThumb, SliderValue, TrackClick,
         UiWidgetsPlugins, observe, slider_self_update,
     },
+    window::{MonitorSelection, PrimaryWindow, WindowMode},
 };
 use bevy_persistent::prelude::*;
 use bevy_seedling::prelude::*;
-use rand::seq::IndexedRandom;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::fonts::{SANS_FONT_PATH, SERIF_FONT_PATH};
+use crate::{
+    CurrentLevel, INITIAL_LEVEL, PlayerCamera,
+    fonts::{SANS_FONT_PATH, SERIF_FONT_PATH},
+    progress::Progress,
+    sprites::{CoinCount, PlayerFocus, TargetStart},
+    text_parse::parse_random_text,
+};
 
 const SLIDER_TRACK: Color = Color::oklcha(0.5912, 0.1184, 318.87, 0.8);
 const SLIDER_THUMB: Color = Color::oklcha(0.6088, 0.2417, 356.26, 0.92);
@@ -27,32 +36,293 @@ struct UISlider;
 #[derive(Component)]
 struct UISliderThumb;
 
+// Text entity showing a slider's value, and the unit drawn after it
+#[derive(Component)]
+struct ValueLabel(Entity, &'static str);
+
 #[derive(Component)]
-struct ValueLabel(Entity);
+struct FovSlider;
+
+pub(crate) const MIN_FOV_DEG: f32 = 30.0;
+pub(crate) const MAX_FOV_DEG: f32 = 90.0;
+
+#[derive(Component)]
+struct MouseSmoothingSlider;
+
+// past this the view lags too far behind the mouse to be usable
+const MAX_MOUSE_SMOOTHING_PCT: f32 = 90.0;
+
+// Which audio bus a volume slider drives
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum VolumeChannel {
+    Master,
+    Music,
+    Sfx,
+}
+
+// Which on/off setting a toggle button flips
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum ToggleSetting {
+    ReduceMotion,
+    Autosave,
+    SprintToggle,
+    Fullscreen,
+    Subtitles,
+    MouseAccel,
+}
+
+// Player actions that can be rebound to a different key
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum InputAction {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Jump,
+    Sprint,
+    Crouch,
+    Interact,
+}
+
+impl InputAction {
+    const ALL: [InputAction; 8] = [
+        InputAction::Forward,
+        InputAction::Back,
+        InputAction::Left,
+        InputAction::Right,
+        InputAction::Jump,
+        InputAction::Sprint,
+        InputAction::Crouch,
+        InputAction::Interact,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            InputAction::Forward => "forward",
+            InputAction::Back => "back",
+            InputAction::Left => "left",
+            InputAction::Right => "right",
+            InputAction::Jump => "jump",
+            InputAction::Sprint => "sprint",
+            InputAction::Crouch => "crouch",
+            InputAction::Interact => "interact",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct KeyBindings {
+    forward: KeyCode,
+    back: KeyCode,
+    left: KeyCode,
+    right: KeyCode,
+    jump: KeyCode,
+    sprint: KeyCode,
+    crouch: KeyCode,
+    interact: KeyCode,
+}
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            forward: KeyCode::KeyW,
+            back: KeyCode::KeyS,
+            left: KeyCode::KeyA,
+            right: KeyCode::KeyD,
+            jump: KeyCode::Space,
+            sprint: KeyCode::ShiftLeft,
+            crouch: KeyCode::KeyC,
+            interact: KeyCode::KeyF,
+        }
+    }
+}
+
+impl KeyBindings {
+    pub(crate) fn key(&self, action: InputAction) -> KeyCode {
+        match action {
+            InputAction::Forward => self.forward,
+            InputAction::Back => self.back,
+            InputAction::Left => self.left,
+            InputAction::Right => self.right,
+            InputAction::Jump => self.jump,
+            InputAction::Sprint => self.sprint,
+            InputAction::Crouch => self.crouch,
+            InputAction::Interact => self.interact,
+        }
+    }
+
+    fn set_key(&mut self, action: InputAction, key: KeyCode) {
+        match action {
+            InputAction::Forward => self.forward = key,
+            InputAction::Back => self.back = key,
+            InputAction::Left => self.left = key,
+            InputAction::Right => self.right = key,
+            InputAction::Jump => self.jump = key,
+            InputAction::Sprint => self.sprint = key,
+            InputAction::Crouch => self.crouch = key,
+            InputAction::Interact => self.interact = key,
+        }
+    }
+
+    // Other actions already bound to `key`
+    fn conflicts(&self, action: InputAction, key: KeyCode) -> impl Iterator<Item = InputAction> {
+        InputAction::ALL
+            .into_iter()
+            .filter(move |other| *other != action && self.key(*other) == key)
+    }
+}
+
+// Run condition for the bound interact key, clicking works too (see sprites)
+pub(crate) fn interact_key_just_pressed(
+    input: Res<ButtonInput<KeyCode>>,
+    settings: Res<Persistent<GameSettings>>,
+) -> bool {
+    input.just_pressed(settings.key_bindings().key(InputAction::Interact))
+}
 
 // Settings resource to persist
-#[derive(Default, Resource, Serialize, Deserialize, Clone)]
-struct GameSettings {
-    sound_volume: f32,
+#[derive(Resource, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct GameSettings {
+    master_volume: f32,
+    music_volume: f32,
+    sfx_volume: f32,
+    reduce_motion: bool,
+    subtitles: bool,
+    autosave: bool,
+    sprint_toggle: bool,
+    fov: f32,
+    // percent of last frame's look motion carried into this one, 0 is raw input
+    mouse_smoothing: f32,
+    mouse_accel: bool,
+    fullscreen: bool,
+    // windowed size, None leaves it at whatever the window starts with
+    window_resolution: Option<(u32, u32)>,
+    key_bindings: KeyBindings,
+}
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            master_volume: 100.0,
+            music_volume: 100.0,
+            sfx_volume: 50.0,
+            reduce_motion: false,
+            subtitles: false,
+            autosave: true,
+            sprint_toggle: false,
+            fov: 45.0,
+            mouse_smoothing: 0.0,
+            mouse_accel: false,
+            fullscreen: false,
+            window_resolution: None,
+            key_bindings: KeyBindings::default(),
+        }
+    }
+}
+
+impl GameSettings {
+    fn volume(&self, channel: VolumeChannel) -> f32 {
+        match channel {
+            VolumeChannel::Master => self.master_volume,
+            VolumeChannel::Music => self.music_volume,
+            VolumeChannel::Sfx => self.sfx_volume,
+        }
+    }
+
+    fn set_volume(&mut self, channel: VolumeChannel, value: f32) {
+        match channel {
+            VolumeChannel::Master => self.master_volume = value,
+            VolumeChannel::Music => self.music_volume = value,
+            VolumeChannel::Sfx => self.sfx_volume = value,
+        }
+    }
+
+    fn toggle(&self, setting: ToggleSetting) -> bool {
+        match setting {
+            ToggleSetting::ReduceMotion => self.reduce_motion,
+            ToggleSetting::Autosave => self.autosave,
+            ToggleSetting::SprintToggle => self.sprint_toggle,
+            ToggleSetting::Fullscreen => self.fullscreen,
+            ToggleSetting::Subtitles => self.subtitles,
+            ToggleSetting::MouseAccel => self.mouse_accel,
+        }
+    }
+
+    fn set_toggle(&mut self, setting: ToggleSetting, value: bool) {
+        match setting {
+            ToggleSetting::ReduceMotion => self.reduce_motion = value,
+            ToggleSetting::Autosave => self.autosave = value,
+            ToggleSetting::SprintToggle => self.sprint_toggle = value,
+            ToggleSetting::Fullscreen => self.fullscreen = value,
+            ToggleSetting::Subtitles => self.subtitles = value,
+            ToggleSetting::MouseAccel => self.mouse_accel = value,
+        }
+    }
+
+    /// Whether camera/UI motion effects should run. Every motion effect should check
+    /// this rather than reading `reduce_motion` directly.
+    pub(crate) fn motion_enabled(&self) -> bool {
+        !self.reduce_motion
+    }
+
+    pub(crate) fn subtitles_enabled(&self) -> bool {
+        self.subtitles
+    }
+
+    pub(crate) fn autosave_enabled(&self) -> bool {
+        self.autosave
+    }
+
+    /// Base vertical field of view in degrees
+    pub(crate) fn fov(&self) -> f32 {
+        self.fov.clamp(MIN_FOV_DEG, MAX_FOV_DEG)
+    }
+
+    /// Fraction of the previous look delta blended into the next one, 0 to 0.9.
+    /// Always 0 with reduced motion, the lag reads as the camera drifting on its own
+    pub(crate) fn mouse_smoothing(&self) -> f32 {
+        if !self.motion_enabled() {
+            return 0.0;
+        }
+        self.mouse_smoothing.clamp(0.0, MAX_MOUSE_SMOOTHING_PCT) / 100.0
+    }
+
+    pub(crate) fn mouse_accel_enabled(&self) -> bool {
+        self.mouse_accel
+    }
+
+    pub(crate) fn sprint_toggle_enabled(&self) -> bool {
+        self.sprint_toggle
+    }
+
+    pub(crate) fn key_bindings(&self) -> &KeyBindings {
+        &self.key_bindings
+    }
+}
+
+// Where persisted files (settings, progress) live
+pub(crate) fn settings_dir() -> PathBuf {
+    dirs::config_dir()
+        .map(|native_config_dir| native_config_dir.join(env!("CARGO_PKG_NAME")))
+        .unwrap_or(Path::new("local").join("config"))
 }
 
 pub struct MenuPlugin;
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         // Setup persistent settings
-        let settings_dir = dirs::config_dir()
-            .map(|native_config_dir| native_config_dir.join(env!("CARGO_PKG_NAME")))
-            .unwrap_or(Path::new("local").join("config"));
-        app.insert_state(GameState::Loading) // initial loading state will get set to in game once player location is initially set
+        let settings_dir = settings_dir();
+        app.insert_state(GameState::Title) // pressing start on the title screen moves to loading, which gets set to in game once player location is initially set
             .insert_resource(
                 Persistent::<GameSettings>::builder()
                     .name("game settings")
                     .format(StorageFormat::Toml)
                     .path(settings_dir.join("settings.toml"))
-                    .default(GameSettings { sound_volume: 50.0 })
+                    .default(GameSettings::default())
                     .build()
                     .expect("failed to initialize game settings"),
             )
+            .init_resource::<Rebinding>()
             .add_plugins((UiWidgetsPlugins, InputDispatchPlugin, TabNavigationPlugin))
             .add_systems(Startup, load_initial_settings)
             .add_systems(
@@ -63,16 +333,26 @@ impl Plugin for MenuPlugin {
                     update_value_labels,
                     update_volume,
                     save_settings_on_change,
+                    save_fov_on_change,
+                    save_mouse_smoothing_on_change,
+                    handle_toggle_clicks,
+                    handle_rebind_clicks,
+                    listen_for_rebind,
+                    handle_title_buttons.run_if(in_state(GameState::Title)),
+                    handle_resolution_clicks,
                 ),
             )
-            .add_systems(OnEnter(GameState::Menu), spawn_menu)
-            .add_systems(OnExit(GameState::Menu), kill_menu);
+            .add_systems(OnEnter(GameState::Title), spawn_title_screen)
+            .add_systems(OnExit(GameState::Title), kill_title_screen)
+            .add_systems(OnEnter(GameState::Menu), (spawn_menu, pause_world))
+            .add_systems(OnExit(GameState::Menu), (kill_menu, resume_world));
     }
 }
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 pub enum GameState {
     #[default]
+    Title,
     Loading,
     Menu,
     InGame,
@@ -88,6 +368,134 @@ fn toggle_menu(
             GameState::InGame => NextState::Pending(GameState::Menu),
             GameState::Menu => NextState::Pending(GameState::InGame),
             GameState::Loading => NextState::Pending(GameState::Loading),
+            GameState::Title => NextState::Pending(GameState::Title),
+        }
+    }
+}
+
+// Freeze gameplay time behind the menu. Gameplay systems are already gated on
+// GameState::InGame, this stops timers and physics too. Audio isn't tied to
+// virtual time so the music keeps going.
+fn pause_world(mut time: ResMut<Time<Virtual>>, mut physics_time: ResMut<Time<Physics>>) {
+    time.pause();
+    physics_time.pause();
+}
+
+fn resume_world(mut time: ResMut<Time<Virtual>>, mut physics_time: ResMut<Time<Physics>>) {
+    time.unpause();
+    physics_time.unpause();
+}
+
+#[derive(Component)]
+struct TitleScreen;
+
+#[derive(Component, Clone, Copy)]
+enum TitleButton {
+    Continue,
+    NewGame,
+    Quit,
+}
+
+fn spawn_title_screen(
+    mut commands: Commands,
+    server: Res<AssetServer>,
+    progress: Res<Persistent<Progress>>,
+) {
+    // only offer to continue if there's somewhere to continue from
+    let mut buttons = vec![];
+    if !progress.last_level.is_empty() {
+        buttons.push(("continue", TitleButton::Continue));
+    }
+    buttons.push(("new game", TitleButton::NewGame));
+    buttons.push(("quit", TitleButton::Quit));
+
+    commands.spawn((Camera2d, IsDefaultUiCamera, TitleScreen));
+    commands
+        .spawn((
+            TitleScreen,
+            Node {
+                position_type: PositionType::Absolute,
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: px(20),
+                top: px(0),
+                left: px(0),
+                right: px(0),
+                bottom: px(0),
+                ..default()
+            },
+            BackgroundColor {
+                0: Color::Oklcha(Oklcha::new(0.1788, 0.0099, 288.85, 1.0)),
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("rat nightmare!"),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: server.load(SANS_FONT_PATH),
+                    font_size: 72.0,
+                    ..default()
+                },
+            ));
+            for (label, button) in buttons {
+                parent.spawn((
+                    Button,
+                    button,
+                    Node {
+                        padding: UiRect::axes(px(24), px(8)),
+                        border_radius: BorderRadius::all(px(3)),
+                        ..default()
+                    },
+                    BackgroundColor(SLIDER_TRACK),
+                    children![(
+                        Text::new(label),
+                        TextColor(TEXT_COLOR),
+                        TextFont {
+                            font: server.load(SANS_FONT_PATH),
+                            font_size: 32.0,
+                            ..default()
+                        },
+                    )],
+                ));
+            }
+        });
+}
+
+fn kill_title_screen(title_ents: Query<Entity, With<TitleScreen>>, mut commands: Commands) {
+    for ent in &title_ents {
+        commands.entity(ent).despawn();
+    }
+}
+
+fn handle_title_buttons(
+    buttons: Query<(&Interaction, &TitleButton), (Changed<Interaction>, With<Button>)>,
+    progress: Res<Persistent<Progress>>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut target_start: ResMut<TargetStart>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut app_exit: MessageWriter<AppExit>,
+) {
+    for (interaction, button) in &buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            TitleButton::Continue => {
+                current_level.0 = progress.last_level.clone();
+                target_start.0 = Some(progress.last_start.clone()).filter(|name| !name.is_empty());
+                next_state.set(GameState::Loading);
+            }
+            TitleButton::NewGame => {
+                current_level.0 = INITIAL_LEVEL.to_string();
+                target_start.0 = None;
+                next_state.set(GameState::Loading);
+            }
+            TitleButton::Quit => {
+                app_exit.write(AppExit::Success);
+            }
         }
     }
 }
@@ -99,11 +507,9 @@ struct Menu;
 
 fn spawn_menu(
     mut commands: Commands,
-    sound_settings: Single<&VolumeNode, With<SoundEffectsBus>>,
+    settings: Res<Persistent<GameSettings>>,
     server: Res<AssetServer>,
 ) {
-    let current_sound_vol = sound_settings.volume.percent();
-
     // Spawn the menu ui elements
     commands.spawn((
         Camera2d,
@@ -161,59 +567,95 @@ fn spawn_menu(
                 },
             )],
         ));
-        parent.spawn((
-            Node {
-                padding: UiRect::axes(px(40), px(10)),
-                ..default()
-            },
-            children![(
-                Text::new("sound"),
-                TextColor(TEXT_COLOR),
-                TextFont {
-                    font: server.load(SANS_FONT_PATH),
-                    font_size: 32.0,
-                    ..default()
-                },
-            )],
-        ));
-        parent
-            .spawn((Node {
-                padding: UiRect::axes(px(50), px(10)),
-                flex_direction: FlexDirection::Row,
-                justify_content: JustifyContent::SpaceBetween,
-                align_items: AlignItems::Center,
-                width: percent(100),
-                ..default()
-            },))
-            .with_children(|subparent| {
-                subparent.spawn(((
-                    Text::new("volume"),
-                    TextColor(TEXT_COLOR),
-                    TextFont {
-                        font: server.load(SANS_FONT_PATH),
-                        font_size: 20.0,
-                        ..default()
-                    },
-                ),));
-
-                let volume_label = subparent
-                    .spawn(((
-                        Text::new(format!("{current_sound_vol}%")),
-                        TextColor(TEXT_COLOR),
-                        TextFont {
-                            font: server.load(SANS_FONT_PATH),
-                            font_size: 28.0,
-                            ..default()
-                        },
-                    ),))
-                    .id();
-
-                subparent.spawn((
-                    horizontal_slider(current_sound_vol),
-                    ValueLabel(volume_label),
-                    observe(slider_self_update),
-                ));
-            });
+        // up top so nobody has to scroll past camera settings to find it
+        menu_heading(parent, &server, "accessibility");
+        toggle_row(
+            parent,
+            &server,
+            "reduce motion",
+            ToggleSetting::ReduceMotion,
+            settings.toggle(ToggleSetting::ReduceMotion),
+        );
+        toggle_row(
+            parent,
+            &server,
+            "subtitles",
+            ToggleSetting::Subtitles,
+            settings.toggle(ToggleSetting::Subtitles),
+        );
+        menu_heading(parent, &server, "sound");
+        for (label, channel) in [
+            ("master", VolumeChannel::Master),
+            ("music", VolumeChannel::Music),
+            ("sfx", VolumeChannel::Sfx),
+        ] {
+            slider_row(
+                parent,
+                &server,
+                label,
+                settings.volume(channel),
+                SliderRange::new(0.0, 100.0),
+                "%",
+                channel,
+            );
+        }
+        menu_heading(parent, &server, "camera");
+        slider_row(
+            parent,
+            &server,
+            "field of view",
+            settings.fov(),
+            SliderRange::new(MIN_FOV_DEG, MAX_FOV_DEG),
+            "°",
+            FovSlider,
+        );
+        slider_row(
+            parent,
+            &server,
+            "mouse smoothing",
+            settings.mouse_smoothing,
+            SliderRange::new(0.0, MAX_MOUSE_SMOOTHING_PCT),
+            "%",
+            MouseSmoothingSlider,
+        );
+        toggle_row(
+            parent,
+            &server,
+            "mouse acceleration",
+            ToggleSetting::MouseAccel,
+            settings.toggle(ToggleSetting::MouseAccel),
+        );
+        // the canvas is sized by the page on web, nothing to set there
+        if cfg!(not(target_family = "wasm")) {
+            menu_heading(parent, &server, "display");
+            toggle_row(
+                parent,
+                &server,
+                "fullscreen",
+                ToggleSetting::Fullscreen,
+                settings.toggle(ToggleSetting::Fullscreen),
+            );
+            resolution_row(parent, &server, settings.window_resolution);
+        }
+        menu_heading(parent, &server, "game");
+        toggle_row(
+            parent,
+            &server,
+            "autosave",
+            ToggleSetting::Autosave,
+            settings.toggle(ToggleSetting::Autosave),
+        );
+        menu_heading(parent, &server, "controls");
+        toggle_row(
+            parent,
+            &server,
+            "toggle sprint",
+            ToggleSetting::SprintToggle,
+            settings.toggle(ToggleSetting::SprintToggle),
+        );
+        for action in InputAction::ALL {
+            rebind_row(parent, &server, action, settings.key_bindings().key(action));
+        }
         parent.spawn((
             Node {
                 padding: UiRect::all(px(20)),
@@ -277,7 +719,250 @@ fn spawn_menu(
     });
 }
 
-fn kill_menu(menu_entity: Query<Entity, With<Menu>>, mut commands: Commands) {
+fn menu_heading(parent: &mut ChildSpawnerCommands, server: &AssetServer, text: &str) {
+    parent.spawn((
+        Node {
+            padding: UiRect::axes(px(40), px(10)),
+            ..default()
+        },
+        children![(
+            Text::new(text),
+            TextColor(TEXT_COLOR),
+            TextFont {
+                font: server.load(SANS_FONT_PATH),
+                font_size: 32.0,
+                ..default()
+            },
+        )],
+    ));
+}
+
+fn toggle_row(
+    parent: &mut ChildSpawnerCommands,
+    server: &AssetServer,
+    label: &str,
+    setting: ToggleSetting,
+    current_val: bool,
+) {
+    parent.spawn((
+        Node {
+            padding: UiRect::axes(px(50), px(10)),
+            flex_direction: FlexDirection::Row,
+            justify_content: JustifyContent::SpaceBetween,
+            align_items: AlignItems::Center,
+            width: percent(100),
+            ..default()
+        },
+        children![
+            (
+                Text::new(label),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: server.load(SANS_FONT_PATH),
+                    font_size: 20.0,
+                    ..default()
+                },
+            ),
+            (
+                Button,
+                setting,
+                Node {
+                    padding: UiRect::axes(px(12), px(4)),
+                    border_radius: BorderRadius::all(px(3)),
+                    ..default()
+                },
+                BackgroundColor(SLIDER_TRACK),
+                children![(
+                    Text::new(toggle_label(current_val)),
+                    TextColor(TEXT_COLOR),
+                    TextFont {
+                        font: server.load(SANS_FONT_PATH),
+                        font_size: 20.0,
+                        ..default()
+                    },
+                )],
+            )
+        ],
+    ));
+}
+
+// Windowed sizes the resolution button cycles through, None being the startup size
+const WINDOW_RESOLUTIONS: [Option<(u32, u32)>; 4] = [
+    None,
+    Some((1280, 720)),
+    Some((1600, 900)),
+    Some((1920, 1080)),
+];
+
+#[derive(Component)]
+struct ResolutionButton;
+
+fn resolution_row(
+    parent: &mut ChildSpawnerCommands,
+    server: &AssetServer,
+    current: Option<(u32, u32)>,
+) {
+    parent.spawn((
+        Node {
+            padding: UiRect::axes(px(50), px(10)),
+            flex_direction: FlexDirection::Row,
+            justify_content: JustifyContent::SpaceBetween,
+            align_items: AlignItems::Center,
+            width: percent(100),
+            ..default()
+        },
+        children![
+            (
+                Text::new("window size"),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: server.load(SANS_FONT_PATH),
+                    font_size: 20.0,
+                    ..default()
+                },
+            ),
+            (
+                Button,
+                ResolutionButton,
+                Node {
+                    padding: UiRect::axes(px(12), px(4)),
+                    border_radius: BorderRadius::all(px(3)),
+                    ..default()
+                },
+                BackgroundColor(SLIDER_TRACK),
+                children![(
+                    Text::new(resolution_label(current)),
+                    TextColor(TEXT_COLOR),
+                    TextFont {
+                        font: server.load(SANS_FONT_PATH),
+                        font_size: 20.0,
+                        ..default()
+                    },
+                )],
+            )
+        ],
+    ));
+}
+
+fn resolution_label(resolution: Option<(u32, u32)>) -> String {
+    match resolution {
+        Some((width, height)) => format!("{width}x{height}"),
+        None => "default".to_string(),
+    }
+}
+
+fn toggle_label(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+fn rebind_row(
+    parent: &mut ChildSpawnerCommands,
+    server: &AssetServer,
+    action: InputAction,
+    current_key: KeyCode,
+) {
+    parent.spawn((
+        Node {
+            padding: UiRect::axes(px(50), px(10)),
+            flex_direction: FlexDirection::Row,
+            justify_content: JustifyContent::SpaceBetween,
+            align_items: AlignItems::Center,
+            width: percent(100),
+            ..default()
+        },
+        children![
+            (
+                Text::new(action.label()),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: server.load(SANS_FONT_PATH),
+                    font_size: 20.0,
+                    ..default()
+                },
+            ),
+            (
+                Button,
+                action,
+                Node {
+                    padding: UiRect::axes(px(12), px(4)),
+                    border_radius: BorderRadius::all(px(3)),
+                    ..default()
+                },
+                BackgroundColor(SLIDER_TRACK),
+                children![(
+                    Text::new(key_label(current_key)),
+                    TextColor(TEXT_COLOR),
+                    TextFont {
+                        font: server.load(SANS_FONT_PATH),
+                        font_size: 20.0,
+                        ..default()
+                    },
+                )],
+            )
+        ],
+    ));
+}
+
+fn key_label(key: KeyCode) -> String {
+    format!("{key:?}")
+}
+
+fn slider_row(
+    parent: &mut ChildSpawnerCommands,
+    server: &AssetServer,
+    label: &str,
+    current_val: f32,
+    range: SliderRange,
+    unit: &'static str,
+    marker: impl Component,
+) {
+    parent
+        .spawn((Node {
+            padding: UiRect::axes(px(50), px(10)),
+            flex_direction: FlexDirection::Row,
+            justify_content: JustifyContent::SpaceBetween,
+            align_items: AlignItems::Center,
+            width: percent(100),
+            ..default()
+        },))
+        .with_children(|subparent| {
+            subparent.spawn(((
+                Text::new(label),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: server.load(SANS_FONT_PATH),
+                    font_size: 20.0,
+                    ..default()
+                },
+            ),));
+
+            let value_label = subparent
+                .spawn(((
+                    Text::new(format!("{current_val:.0}{unit}")),
+                    TextColor(TEXT_COLOR),
+                    TextFont {
+                        font: server.load(SANS_FONT_PATH),
+                        font_size: 28.0,
+                        ..default()
+                    },
+                ),))
+                .id();
+
+            subparent.spawn((
+                horizontal_slider(current_val, range),
+                ValueLabel(value_label, unit),
+                marker,
+                observe(slider_self_update),
+            ));
+        });
+}
+
+fn kill_menu(
+    menu_entity: Query<Entity, With<Menu>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut commands: Commands,
+) {
+    rebinding.0 = None;
     for ent in menu_entity {
         let mut menu_ent = commands.entity(ent);
         menu_ent.despawn();
@@ -306,7 +991,7 @@ fn menu() -> impl Bundle {
     )
 }
 
-fn horizontal_slider(initial_val: f32) -> impl Bundle {
+fn horizontal_slider(initial_val: f32, range: SliderRange) -> impl Bundle {
     (
         Node {
             display: Display::Flex,
@@ -324,7 +1009,7 @@ fn horizontal_slider(initial_val: f32) -> impl Bundle {
             track_click: TrackClick::Snap,
         },
         SliderValue(initial_val),
-        SliderRange::new(0.0, 100.0),
+        range,
         TabIndex(0),
         Children::spawn((
             Spawn((
@@ -410,46 +1095,481 @@ fn update_value_labels(
 ) {
     for (value, label) in sliders.iter() {
         if let Ok(mut text) = texts.get_mut(label.0) {
-            **text = format!("{:.0}%", value.0);
+            **text = format!("{:.0}{}", value.0, label.1);
+        }
+    }
+}
+
+type VolumeBuses<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut VolumeNode,
+        Has<MainBus>,
+        Has<SamplerPool<MusicPool>>,
+        Has<SoundEffectsBus>,
+    ),
+    Or<(
+        With<MainBus>,
+        With<SamplerPool<MusicPool>>,
+        With<SoundEffectsBus>,
+    )>,
+>;
+
+fn set_channel_volume(buses: &mut VolumeBuses, channel: VolumeChannel, percent: f32) {
+    for (mut volume, is_main, is_music, is_sfx) in buses.iter_mut() {
+        let is_channel = match channel {
+            VolumeChannel::Master => is_main,
+            VolumeChannel::Music => is_music,
+            VolumeChannel::Sfx => is_sfx,
+        };
+        if is_channel {
+            volume.as_mut().set_percent(percent);
         }
     }
 }
 
 fn update_volume(
-    sliders: Query<&SliderValue, (Changed<SliderValue>, With<UISlider>)>,
-    mut sound_settings: Single<&mut VolumeNode, With<SoundEffectsBus>>,
+    sliders: Query<(&SliderValue, &VolumeChannel), (Changed<SliderValue>, With<UISlider>)>,
+    mut buses: VolumeBuses,
 ) {
-    for value in sliders.iter() {
-        sound_settings.as_mut().set_percent(value.0);
+    for (value, channel) in sliders.iter() {
+        set_channel_volume(&mut buses, *channel, value.0);
     }
 }
 
-// Load settings on startup and apply to audio
+// Load settings on startup and apply to audio and the window. Reduced motion doesn't
+// need applying here, shake/sprint fov/smoothing check `motion_enabled` every frame
 fn load_initial_settings(
     settings: Res<Persistent<GameSettings>>,
-    mut sound_settings: Single<&mut VolumeNode, With<SoundEffectsBus>>,
+    mut buses: VolumeBuses,
+    mut window: Query<&mut Window, With<PrimaryWindow>>,
 ) {
-    // set sound volume
-    sound_settings.as_mut().set_percent(settings.sound_volume);
+    for channel in [
+        VolumeChannel::Master,
+        VolumeChannel::Music,
+        VolumeChannel::Sfx,
+    ] {
+        set_channel_volume(&mut buses, channel, settings.volume(channel));
+    }
+    if let Ok(mut window) = window.single_mut() {
+        apply_window_settings(&settings, &mut window);
+    }
+}
+
+fn apply_window_settings(settings: &GameSettings, window: &mut Window) {
+    apply_window_mode(settings, window);
+    apply_window_resolution(settings, window);
+}
+
+// The two halves get applied on their own from the menu, so changing one setting doesn't
+// snap a window the player resized by hand back to the saved size
+fn apply_window_mode(settings: &GameSettings, window: &mut Window) {
+    // web uses fit_canvas_to_parent instead
+    if cfg!(target_family = "wasm") {
+        return;
+    }
+    window.mode = if settings.fullscreen {
+        WindowMode::BorderlessFullscreen(MonitorSelection::Current)
+    } else {
+        WindowMode::Windowed
+    };
+}
+
+fn apply_window_resolution(settings: &GameSettings, window: &mut Window) {
+    if cfg!(target_family = "wasm") {
+        return;
+    }
+    if let Some((width, height)) = settings.window_resolution {
+        window.resolution.set(width as f32, height as f32);
+    }
+}
+
+fn handle_resolution_clicks(
+    buttons: Query<(&Interaction, &Children), (Changed<Interaction>, With<ResolutionButton>)>,
+    mut texts: Query<&mut Text>,
+    mut settings: ResMut<Persistent<GameSettings>>,
+    mut window: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    for (interaction, children) in &buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let current = WINDOW_RESOLUTIONS
+            .iter()
+            .position(|resolution| *resolution == settings.window_resolution)
+            .unwrap_or(0);
+        settings.window_resolution = WINDOW_RESOLUTIONS[(current + 1) % WINDOW_RESOLUTIONS.len()];
+        set_button_text(
+            children,
+            &mut texts,
+            resolution_label(settings.window_resolution),
+        );
+        if let Ok(mut window) = window.single_mut() {
+            apply_window_resolution(&settings, &mut window);
+        }
+        if let Err(e) = settings.persist() {
+            error!("Failed to save settings: {}", e);
+        }
+    }
 }
 
 fn save_settings_on_change(
-    sliders: Query<&SliderValue, (Changed<SliderValue>, With<UISlider>)>,
+    sliders: Query<(&SliderValue, &VolumeChannel), (Changed<SliderValue>, With<UISlider>)>,
+    mut settings: ResMut<Persistent<GameSettings>>,
+) {
+    if sliders.is_empty() {
+        return;
+    }
+    for (value, channel) in sliders.iter() {
+        settings.set_volume(*channel, value.0);
+    }
+    if let Err(e) = settings.persist() {
+        error!("Failed to save settings: {}", e);
+    }
+}
+
+fn save_fov_on_change(
+    sliders: Query<&SliderValue, (Changed<SliderValue>, With<FovSlider>)>,
     mut settings: ResMut<Persistent<GameSettings>>,
 ) {
-    for value in sliders.iter() {
-        settings.sound_volume = value.0;
+    let Some(value) = sliders.iter().last() else {
+        return;
+    };
+    settings.fov = value.0;
+    if let Err(e) = settings.persist() {
+        error!("Failed to save settings: {}", e);
+    }
+}
+
+fn save_mouse_smoothing_on_change(
+    sliders: Query<&SliderValue, (Changed<SliderValue>, With<MouseSmoothingSlider>)>,
+    mut settings: ResMut<Persistent<GameSettings>>,
+) {
+    let Some(value) = sliders.iter().last() else {
+        return;
+    };
+    settings.mouse_smoothing = value.0;
+    if let Err(e) = settings.persist() {
+        error!("Failed to save settings: {}", e);
+    }
+}
+
+fn handle_toggle_clicks(
+    buttons: Query<(&Interaction, &ToggleSetting, &Children), (Changed<Interaction>, With<Button>)>,
+    mut texts: Query<&mut Text>,
+    mut settings: ResMut<Persistent<GameSettings>>,
+    mut window: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    for (interaction, setting, children) in &buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let value = !settings.toggle(*setting);
+        settings.set_toggle(*setting, value);
+        if *setting == ToggleSetting::Fullscreen
+            && let Ok(mut window) = window.single_mut()
+        {
+            apply_window_mode(&settings, &mut window);
+        }
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(child) {
+                **text = toggle_label(value).to_string();
+            }
+        }
         if let Err(e) = settings.persist() {
             error!("Failed to save settings: {}", e);
         }
     }
 }
 
+// The action waiting on a key press to be rebound, if any
+#[derive(Resource, Default)]
+struct Rebinding(Option<InputAction>);
+
+fn set_button_text(children: &Children, texts: &mut Query<&mut Text>, value: String) {
+    for child in children.iter() {
+        if let Ok(mut text) = texts.get_mut(child) {
+            **text = value.clone();
+        }
+    }
+}
+
+fn handle_rebind_clicks(
+    buttons: Query<(&Interaction, &InputAction, &Children), (Changed<Interaction>, With<Button>)>,
+    mut texts: Query<&mut Text>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    for (interaction, action, children) in &buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        rebinding.0 = Some(*action);
+        set_button_text(children, &mut texts, "press a key...".to_string());
+    }
+}
+
+fn listen_for_rebind(
+    input: Res<ButtonInput<KeyCode>>,
+    buttons: Query<(&InputAction, &Children), With<Button>>,
+    mut texts: Query<&mut Text>,
+    mut rebinding: ResMut<Rebinding>,
+    mut settings: ResMut<Persistent<GameSettings>>,
+) {
+    let Some(action) = rebinding.0 else {
+        return;
+    };
+    // Escape is left alone so it can still close the menu
+    let Some(key) = input
+        .get_just_pressed()
+        .find(|key| **key != KeyCode::Escape)
+        .copied()
+    else {
+        return;
+    };
+    for other in settings.key_bindings.conflicts(action, key) {
+        warn!(
+            "{} is already bound to {}, both actions will share it",
+            key_label(key),
+            other.label()
+        );
+    }
+    settings.key_bindings.set_key(action, key);
+    rebinding.0 = None;
+    for (button_action, children) in &buttons {
+        if *button_action == action {
+            set_button_text(children, &mut texts, key_label(key));
+        }
+    }
+    if let Err(e) = settings.persist() {
+        error!("Failed to save settings: {}", e);
+    }
+}
+
+// Plugin for the always-on bits of UI during play
+pub struct HudPlugin;
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(FrameTimeDiagnosticsPlugin::default())
+            .init_resource::<DebugOverlay>()
+            .add_systems(Startup, (spawn_coin_counter, spawn_debug_overlay))
+            .add_systems(
+                Update,
+                (
+                    update_coin_counter,
+                    toggle_debug_overlay.run_if(input_just_pressed(DEBUG_OVERLAY_KEY)),
+                    update_debug_overlay,
+                ),
+            );
+    }
+}
+
+#[derive(Component)]
+struct CoinCounter;
+
+fn spawn_coin_counter(mut commands: Commands, server: Res<AssetServer>) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: px(16),
+            left: px(16),
+            padding: UiRect::axes(px(12), px(6)),
+            ..default()
+        },
+        BackgroundColor {
+            0: Color::Oklcha(Oklcha::new(0.1788, 0.0099, 288.85, 0.9)),
+        },
+        children![(
+            CoinCounter,
+            Text::new(coin_counter_text(0)),
+            TextColor(TEXT_COLOR),
+            TextFont {
+                font: server.load(SANS_FONT_PATH),
+                font_size: 24.0,
+                ..default()
+            },
+        )],
+    ));
+}
+
+fn update_coin_counter(
+    coin_count: Res<CoinCount>,
+    mut counter_text: Query<&mut Text, With<CoinCounter>>,
+) {
+    if !coin_count.is_changed() {
+        return;
+    }
+    for mut text in &mut counter_text {
+        **text = coin_counter_text(coin_count.0);
+    }
+}
+
+fn coin_counter_text(count: u32) -> String {
+    format!("coins: {count}")
+}
+
+const DEBUG_OVERLAY_KEY: KeyCode = KeyCode::F3;
+
+/// Whether the debug readout in the corner is showing
+#[derive(Resource, Default)]
+struct DebugOverlay(bool);
+
+#[derive(Component)]
+struct DebugOverlayText;
+
+fn spawn_debug_overlay(mut commands: Commands, server: Res<AssetServer>) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: px(16),
+            right: px(16),
+            padding: UiRect::axes(px(12), px(6)),
+            ..default()
+        },
+        Visibility::Hidden,
+        BackgroundColor {
+            0: Color::Oklcha(Oklcha::new(0.1788, 0.0099, 288.85, 0.9)),
+        },
+        children![(
+            DebugOverlayText,
+            Text::default(),
+            TextColor(TEXT_COLOR),
+            TextFont {
+                font: server.load(SANS_FONT_PATH),
+                font_size: 16.0,
+                ..default()
+            },
+        )],
+    ));
+}
+
+fn toggle_debug_overlay(mut overlay: ResMut<DebugOverlay>) {
+    overlay.0 = !overlay.0;
+}
+
+fn update_debug_overlay(
+    overlay: Res<DebugOverlay>,
+    text: Single<(&mut Text, &ChildOf), With<DebugOverlayText>>,
+    mut visibility: Query<&mut Visibility>,
+    player: Query<(&Transform, &LinearVelocity), With<PlayerCamera>>,
+    focus: Res<PlayerFocus>,
+    current_level: Res<CurrentLevel>,
+    diagnostics: Res<DiagnosticsStore>,
+) {
+    let (mut text, child_of) = text.into_inner();
+    if overlay.is_changed()
+        && let Ok(mut visibility) = visibility.get_mut(child_of.parent())
+    {
+        *visibility = if overlay.0 {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+    if !overlay.0 {
+        return;
+    }
+
+    let fps = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.smoothed())
+        .unwrap_or_default();
+    let frame_time = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FRAME_TIME)
+        .and_then(|frame_time| frame_time.smoothed())
+        .unwrap_or_default();
+    let (pos, speed) = player
+        .iter()
+        .next()
+        .map(|(tf, lin_vel)| (tf.translation, lin_vel.0.length()))
+        .unwrap_or_default();
+    let focus_name = focus
+        .0
+        .as_ref()
+        .map(|sprite_deets| sprite_deets.name.as_str())
+        .unwrap_or("-");
+    text.0 = format!(
+        "fps: {fps:.0} ({frame_time:.1}ms)\nmap: {}\npos: {:.2} {:.2} {:.2}\nspeed: {speed:.2}\nfocus: {focus_name}",
+        current_level.0, pos.x, pos.y, pos.z,
+    );
+}
+
 pub struct LoadingPlugin;
 impl Plugin for LoadingPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::Loading), spawn_loading_ui)
-            .add_systems(OnExit(GameState::Loading), despawn_loading_ui);
+            .add_systems(OnExit(GameState::Loading), despawn_loading_ui)
+            .add_systems(Update, update_transition_fades);
+    }
+}
+
+const TRANSITION_FADE_S: f32 = 0.3;
+
+/// Fullscreen black overlay for level transitions. Fades to black, holds there until
+/// `start_fade_out` gets called, then fades back and despawns itself
+#[derive(Component)]
+pub struct TransitionFade {
+    timer: Timer,
+    fading_out: bool,
+}
+
+impl TransitionFade {
+    /// Whether the screen is fully black yet
+    pub fn is_black(&self) -> bool {
+        !self.fading_out && self.timer.is_finished()
+    }
+
+    pub fn start_fade_out(&mut self) {
+        if !self.fading_out {
+            self.fading_out = true;
+            self.timer.reset();
+        }
+    }
+}
+
+pub(crate) fn transition_fade(settings: &GameSettings) -> impl Bundle {
+    // no fade with reduced motion, just cut
+    let duration = if settings.motion_enabled() {
+        TRANSITION_FADE_S
+    } else {
+        0.0
+    };
+    (
+        Node {
+            position_type: PositionType::Absolute,
+            top: px(0),
+            left: px(0),
+            right: px(0),
+            bottom: px(0),
+            ..default()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.0)),
+        // under the rest of the ui so loading text still shows on top
+        GlobalZIndex(-1),
+        TransitionFade {
+            timer: Timer::from_seconds(duration, TimerMode::Once),
+            fading_out: false,
+        },
+    )
+}
+
+pub(crate) fn update_transition_fades(
+    mut fades: Query<(Entity, &mut TransitionFade, &mut BackgroundColor)>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (ent, mut fade, mut background) in &mut fades {
+        fade.timer.tick(time.delta());
+        let alpha = if fade.fading_out {
+            fade.timer.fraction_remaining()
+        } else {
+            fade.timer.fraction()
+        };
+        background.0 = background.0.with_alpha(alpha);
+
+        if fade.fading_out && fade.timer.is_finished() {
+            commands.entity(ent).despawn();
+        }
     }
 }
 
@@ -508,63 +1628,7 @@ fn despawn_loading_ui(mut commands: Commands, loading_ents: Query<Entity, With<L
 }
 
 fn get_loading_string() -> String {
-    let mut rng = rand::rng();
-    let words = vec![
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "not loading?",
-        "now loafing",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "they never ask 'how loading?'  :(",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now larping",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "loading now",
-        "now yodeling",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "now loading",
-        "loading makes me feel good",
-    ];
-    words.choose(&mut rng).unwrap().to_string()
+    parse_random_text(
+        "<now loading:48|entering the hole...:10|not loading?|now loafing|they never ask 'how loading?'  :(|now larping|loading now|now yodeling|loading makes me feel good>",
+    )
 }